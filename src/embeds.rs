@@ -0,0 +1,253 @@
+use std::time::Duration;
+
+use serenity::builder::{CreateComponents, CreateEmbed};
+use serenity::model::application::component::ButtonStyle;
+
+use crate::history::PlayHistory;
+use crate::models::Song;
+use crate::queue::GuildQueue;
+use crate::saved_playlists::SavedPlaylist;
+use crate::timestamps::{format_duration, format_length};
+
+const PROGRESS_BAR_WIDTH: usize = 20;
+const QUEUE_PAGE_SIZE: usize = 10;
+const HISTORY_EMBED_SIZE: usize = 10;
+
+/// Custom ID prefix of the queue buttons, followed by the index of the page they lead to.
+pub const QUEUE_PAGE_BUTTON_PREFIX: &str = "queue_page:";
+
+/// Custom ID of the menu used to pick a search result. Its values are the indexes of the results.
+pub const SEARCH_MENU_ID: &str = "search_result";
+/// Discord rejects longer labels in menus and autocomplete choices.
+pub const MAX_LABEL_LENGTH: usize = 100;
+
+/// Fills the embed used to announce a song and by the now playing command. The progress bar is only shown when the
+/// position of the track is known.
+pub fn now_playing_embed<'a>(
+    embed: &'a mut CreateEmbed,
+    song: &Song,
+    position: Option<Duration>,
+    next_song: Option<&Song>,
+) -> &'a mut CreateEmbed {
+    let progress = match (position, song.duration) {
+        (Some(position), Some(duration)) => format!(
+            "`{}` {} `{}`",
+            format_duration(position),
+            progress_bar(position, duration),
+            format_duration(duration),
+        ),
+        (Some(position), None) => format!("`{}`", format_duration(position)),
+        (None, Some(duration)) => format!("Duration: `{}`", format_duration(duration)),
+        (None, None) => String::new(),
+    };
+
+    let next_up = match next_song {
+        Some(song) => format!("[{}]({})", song.title, song.url),
+        None => "Nothing, the queue is empty".to_string(),
+    };
+
+    embed
+        .author(|a| a.name("Now playing"))
+        .title(&song.title)
+        .url(&song.url)
+        .description(progress);
+
+    if let Some(requester) = &song.requester {
+        let queued_at = song.queued_at
+            .map(|queued_at| format!(" <t:{}:R>", queued_at.unix_timestamp()))
+            .unwrap_or_default();
+
+        embed.field("Requested by", format!("{}{queued_at}", requester.display_name), false);
+    }
+
+    embed.field("Next up", next_up, false);
+
+    if let Some(thumbnail) = &song.thumbnail {
+        embed.thumbnail(thumbnail);
+    }
+
+    embed
+}
+
+pub fn queue_pages_count(queue: &GuildQueue) -> usize {
+    queue.len().div_ceil(QUEUE_PAGE_SIZE).max(1)
+}
+
+/// Builds a page of the queue, including when each song is expected to start. `current_remaining` is the time left
+/// of the current track, unknown for live streams.
+pub fn queue_embed(queue: &GuildQueue, current_remaining: Option<Duration>, page: usize) -> CreateEmbed {
+    let first_index = page * QUEUE_PAGE_SIZE;
+    let mut lines: Vec<String> = Vec::with_capacity(QUEUE_PAGE_SIZE);
+
+    // Once a duration is unknown, the time until the next songs start is unknown too
+    let mut eta = current_remaining;
+    let mut known_remaining = current_remaining.unwrap_or_default();
+
+    for (index, song) in queue.iter().enumerate() {
+        if index >= first_index && index < first_index + QUEUE_PAGE_SIZE {
+            let duration = format_length(song.duration);
+            let eta_text = eta.map(format_duration).unwrap_or("?".to_string());
+
+            let requester = song.requester
+                .as_ref()
+                .map(|requester| format!(" • {}", requester.display_name))
+                .unwrap_or_default();
+
+            lines.push(format!("`{}.` [{}]({}) `{duration}` • in {eta_text}{requester}", index + 1, song.title, song.url));
+        }
+
+        eta = eta.zip(song.duration).map(|(eta, duration)| eta + duration);
+        known_remaining += song.duration.unwrap_or_default();
+    }
+
+    let total_remaining = match eta {
+        Some(total_remaining) => format_duration(total_remaining),
+        None => format!("{}+", format_duration(known_remaining)),
+    };
+
+    let mut embed = CreateEmbed::default();
+
+    embed
+        .title("Queue")
+        .description(lines.join("\n"))
+        .footer(|f| {
+            f.text(format!(
+                "Page {}/{} • {} songs • {total_remaining} remaining",
+                page + 1,
+                queue_pages_count(queue),
+                queue.len(),
+            ))
+        });
+
+    embed
+}
+
+/// Lists the last played songs, from the most recent one, along with who requested them.
+pub fn history_embed(history: &PlayHistory) -> CreateEmbed {
+    let lines: Vec<String> = history.recent()
+        .take(HISTORY_EMBED_SIZE)
+        .enumerate()
+        .map(|(index, song)| {
+            let requester = song.requester
+                .as_ref()
+                .map(|requester| format!(" • {}", requester.display_name))
+                .unwrap_or_default();
+
+            format!("`{}.` [{}]({}) `{}`{requester}", index + 1, song.title, song.url, format_length(song.duration))
+        })
+        .collect();
+
+    let mut embed = CreateEmbed::default();
+
+    embed
+        .title("Recently played")
+        .description(lines.join("\n"));
+
+    embed
+}
+
+/// Lists the saved playlists of the server and the ones of the user, along with how many songs they have.
+pub fn saved_playlists_embed(server_playlists: &[(&str, &SavedPlaylist)], user_playlists: &[(&str, &SavedPlaylist)]) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+
+    embed
+        .title("Saved playlists")
+        .description(format!(
+            "**Server**\n{}\n\n**Yours** (`--mine`)\n{}",
+            saved_playlists_lines(server_playlists),
+            saved_playlists_lines(user_playlists),
+        ));
+
+    embed
+}
+
+fn saved_playlists_lines(playlists: &[(&str, &SavedPlaylist)]) -> String {
+    if playlists.is_empty() {
+        return "None".to_string();
+    }
+
+    let lines: Vec<String> = playlists
+        .iter()
+        .map(|(name, playlist)| format!("`{name}` • {} songs", playlist.songs.len()))
+        .collect();
+
+    lines.join("\n")
+}
+
+/// Adds the buttons to go to the previous and next pages of the queue.
+pub fn queue_page_buttons(components: &mut CreateComponents, page: usize, pages_count: usize) -> &mut CreateComponents {
+    components.create_action_row(|row| {
+        row
+            .create_button(|b| {
+                b.custom_id(format!("{QUEUE_PAGE_BUTTON_PREFIX}{}", page.saturating_sub(1)))
+                    .label("◀ Previous")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page == 0)
+            })
+            .create_button(|b| {
+                b.custom_id(format!("{QUEUE_PAGE_BUTTON_PREFIX}{}", page + 1))
+                    .label("Next ▶")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page + 1 >= pages_count)
+            })
+    })
+}
+
+/// Lists the results of a search, numbered from 1 so they match the options of the menu.
+pub fn search_results_embed(songs: &[Song]) -> CreateEmbed {
+    let lines: Vec<String> = songs
+        .iter()
+        .enumerate()
+        .map(|(index, song)| {
+            let duration = format_length(song.duration);
+
+            format!("`{}.` [{}]({}) `{duration}`", index + 1, song.title, song.url)
+        })
+        .collect();
+
+    let mut embed = CreateEmbed::default();
+
+    embed
+        .title("Search results")
+        .description(lines.join("\n"))
+        .footer(|f| f.text("Pick the song to queue from the menu below"));
+
+    embed
+}
+
+/// Adds the menu to pick one of the results of a search.
+pub fn search_results_menu<'a>(components: &'a mut CreateComponents, songs: &[Song]) -> &'a mut CreateComponents {
+    components.create_action_row(|row| {
+        row.create_select_menu(|menu| {
+            menu.custom_id(SEARCH_MENU_ID)
+                .placeholder("Pick a song")
+                .options(|options| {
+                    for (index, song) in songs.iter().enumerate() {
+                        options.create_option(|o| {
+                            o.label(truncate(&format!("{}. {}", index + 1, song.title), MAX_LABEL_LENGTH))
+                                .value(index)
+                                .description(format_length(song.duration))
+                        });
+                    }
+
+                    options
+                })
+        })
+    })
+}
+
+/// Cuts a text to the given number of characters, as Discord counts them.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    text.chars().take(max_chars).collect()
+}
+
+fn progress_bar(position: Duration, duration: Duration) -> String {
+    let filled = if duration.is_zero() {
+        0
+    } else {
+        let ratio = position.as_secs_f64() / duration.as_secs_f64();
+        ((ratio * PROGRESS_BAR_WIDTH as f64) as usize).min(PROGRESS_BAR_WIDTH)
+    };
+
+    format!("{}🔘{}", "▬".repeat(filled), "▬".repeat(PROGRESS_BAR_WIDTH - filled))
+}
@@ -0,0 +1,117 @@
+use std::error::Error;
+use std::fmt;
+
+/// Failures of the bot that the user should be told about.
+#[derive(Debug)]
+pub enum DubaError {
+    GuildNotFound,
+    NotInVoiceChannel,
+    AlreadyStopped,
+    ResolverNotFound,
+    /// Nothing could be loaded for a URL or search terms.
+    TrackNotFound { input: String },
+    /// The video is private, deleted or blocked.
+    VideoUnavailable { input: String },
+    AgeRestricted { input: String },
+    PlaylistEmpty { url: String },
+    QueueIndexOutOfRange,
+    InvalidSearchResult,
+    /// An option of the play commands has no value, or it's not a positive number.
+    InvalidPlayOption { option: String },
+    /// The first entry of a playlist range comes after the last one.
+    InvalidPlaylistRange { from: usize, to: usize },
+    /// yt-dlp could not be run at all.
+    YtDlpFailed { reason: String },
+    InvalidPlaylistCommand,
+    InvalidPlaylistName { max_length: usize },
+    SavedPlaylistNotFound { name: String },
+    TooManySavedPlaylists { max: usize },
+    /// The songs would make a saved playlist longer than allowed.
+    SavedPlaylistFull { name: String, max: usize },
+}
+
+impl DubaError {
+    /// Classifies a failure to load the given input from what yt-dlp reported.
+    pub fn from_ytdl_output(input: &str, output: &str) -> Self {
+        let output = output.to_lowercase();
+        let input = input.to_string();
+
+        if output.contains("confirm your age") || output.contains("age-restricted") || output.contains("age restricted") {
+            DubaError::AgeRestricted { input }
+        } else if output.contains("video unavailable") || output.contains("private video") || output.contains("has been removed") {
+            DubaError::VideoUnavailable { input }
+        } else {
+            DubaError::TrackNotFound { input }
+        }
+    }
+
+    /// Short name of the error, used as a field of the log entries.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DubaError::GuildNotFound => "guild_not_found",
+            DubaError::NotInVoiceChannel => "not_in_voice_channel",
+            DubaError::AlreadyStopped => "already_stopped",
+            DubaError::ResolverNotFound => "resolver_not_found",
+            DubaError::TrackNotFound { .. } => "track_not_found",
+            DubaError::VideoUnavailable { .. } => "video_unavailable",
+            DubaError::AgeRestricted { .. } => "age_restricted",
+            DubaError::PlaylistEmpty { .. } => "playlist_empty",
+            DubaError::QueueIndexOutOfRange => "queue_index_out_of_range",
+            DubaError::InvalidSearchResult => "invalid_search_result",
+            DubaError::InvalidPlayOption { .. } => "invalid_play_option",
+            DubaError::InvalidPlaylistRange { .. } => "invalid_playlist_range",
+            DubaError::YtDlpFailed { .. } => "yt_dlp_failed",
+            DubaError::InvalidPlaylistCommand => "invalid_playlist_command",
+            DubaError::InvalidPlaylistName { .. } => "invalid_playlist_name",
+            DubaError::SavedPlaylistNotFound { .. } => "saved_playlist_not_found",
+            DubaError::TooManySavedPlaylists { .. } => "too_many_saved_playlists",
+            DubaError::SavedPlaylistFull { .. } => "saved_playlist_full",
+        }
+    }
+
+    /// Explanation shown to the user in the channel of the command.
+    pub fn user_message(&self) -> String {
+        match self {
+            DubaError::GuildNotFound => "This command only works in a server".to_string(),
+            DubaError::NotInVoiceChannel => "Join a voice channel first".to_string(),
+            DubaError::AlreadyStopped => "o_O Already stopped".to_string(),
+            DubaError::ResolverNotFound | DubaError::YtDlpFailed { .. } => {
+                "Songs can't be loaded right now, try again later".to_string()
+            }
+            DubaError::TrackNotFound { input } => format!("Could not find anything for {input}"),
+            DubaError::VideoUnavailable { input } => {
+                format!("{input} is unavailable, it may be private, deleted or blocked")
+            }
+            DubaError::AgeRestricted { input } => format!("{input} is age-restricted and can't be played"),
+            DubaError::PlaylistEmpty { url } => format!("Could not load any song from the playlist {url}"),
+            DubaError::QueueIndexOutOfRange => "That position is not in the queue".to_string(),
+            DubaError::InvalidSearchResult => "That search result doesn't exist".to_string(),
+            DubaError::InvalidPlayOption { option } => format!("`{option}` needs a number of 1 or more, e.g. `{option} 10`"),
+            DubaError::InvalidPlaylistRange { from, to } => {
+                format!("The playlist range can't start at {from} and end at {to}, use reverse to queue it backwards")
+            }
+            DubaError::InvalidPlaylistCommand => {
+                "Use `playlist save|load|delete NAME`, `playlist add NAME URL|Title` or `playlist list`, adding `--mine` for your own playlists".to_string()
+            }
+            DubaError::InvalidPlaylistName { max_length } => {
+                format!("Playlist names can only have letters, numbers, `-` and `_`, up to {max_length} characters")
+            }
+            DubaError::SavedPlaylistNotFound { name } => format!("There's no saved playlist named **{name}**"),
+            DubaError::TooManySavedPlaylists { max } => {
+                format!("Only {max} playlists can be saved, delete one first")
+            }
+            DubaError::SavedPlaylistFull { name, max } => format!("**{name}** can't have more than {max} songs"),
+        }
+    }
+}
+
+impl fmt::Display for DubaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DubaError::YtDlpFailed { reason } => write!(f, "yt-dlp failed: {reason}"),
+            _ => write!(f, "{}", self.user_message()),
+        }
+    }
+}
+
+impl Error for DubaError {}
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+
+use crate::models::Song;
+
+/// Songs kept in the history of a guild, the oldest ones are forgotten first.
+pub const MAX_HISTORY_SONGS: usize = 50;
+
+/// Songs that have been played in a guild, whether they ended on their own or were skipped.
+#[derive(Default)]
+pub struct PlayHistory {
+    songs: VecDeque<Song>,
+}
+
+impl PlayHistory {
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty()
+    }
+
+    /// Adds a song that has just stopped playing.
+    pub fn record(&mut self, mut song: Song) {
+        // Songs played again start from the beginning
+        song.start = None;
+
+        if self.songs.len() == MAX_HISTORY_SONGS {
+            self.songs.pop_front();
+        }
+
+        self.songs.push_back(song);
+    }
+
+    /// Removes the last played song and returns it.
+    pub fn take_last(&mut self) -> Option<Song> {
+        self.songs.pop_back()
+    }
+
+    /// Iterates over the songs from the most recently played.
+    pub fn recent(&self) -> impl Iterator<Item = &Song> {
+        self.songs.iter().rev()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::models::song;
+
+    #[test]
+    fn forgets_the_oldest_songs_first() {
+        let mut history = PlayHistory::default();
+
+        for index in 0..=MAX_HISTORY_SONGS {
+            history.record(song(&index.to_string()));
+        }
+
+        let titles: Vec<&str> = history.recent().map(|song| song.title.as_str()).collect();
+
+        assert_eq!(titles.len(), MAX_HISTORY_SONGS);
+        assert_eq!(titles.first().copied(), Some(MAX_HISTORY_SONGS.to_string().as_str()));
+        assert_eq!(titles.last().copied(), Some("1"));
+    }
+
+    #[test]
+    fn takes_the_most_recent_song_first() {
+        let mut history = PlayHistory::default();
+        let mut started_song = song("b");
+        started_song.start = Some(Duration::from_secs(30));
+
+        history.record(song("a"));
+        history.record(started_song);
+
+        let last = history.take_last().expect("history should have songs");
+
+        assert_eq!(last.title, "b");
+        assert_eq!(last.start, None);
+        assert_eq!(history.take_last().map(|song| song.title).as_deref(), Some("a"));
+        assert!(history.take_last().is_none());
+        assert!(history.is_empty());
+    }
+}
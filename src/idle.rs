@@ -0,0 +1,232 @@
+use std::env;
+use std::time::Duration;
+
+use serenity::client::Context;
+use serenity::model::id::GuildId;
+use songbird::tracks::PlayMode;
+use tracing::info;
+
+use crate::{check_msg, get_bot_voice_channel, get_server, get_server_mut, get_voice_channel_listeners};
+
+const DEFAULT_ALONE_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_IDLE_TIMEOUT_MINS: u64 = 5;
+
+/// Why the bot leaves a voice channel on its own.
+#[derive(Clone, Copy)]
+pub enum DisconnectReason {
+    /// Everyone else left the voice channel.
+    Alone,
+    /// The queue ended and nothing has been played since.
+    Idle,
+}
+
+impl DisconnectReason {
+    fn message(self) -> &'static str {
+        match self {
+            DisconnectReason::Alone => "Left the voice channel as nobody was listening",
+            DisconnectReason::Idle => "Left the voice channel as nothing was playing",
+        }
+    }
+}
+
+/// How long the bot waits before leaving, read from the DUBA_ALONE_TIMEOUT_SECS and DUBA_IDLE_TIMEOUT_MINS
+/// environment variables.
+pub struct DisconnectTimeouts {
+    pub alone: Duration,
+    pub idle: Duration,
+}
+
+impl DisconnectTimeouts {
+    pub fn from_env() -> Self {
+        let alone_secs = env_number("DUBA_ALONE_TIMEOUT_SECS").unwrap_or(DEFAULT_ALONE_TIMEOUT_SECS);
+        let idle_mins = env_number("DUBA_IDLE_TIMEOUT_MINS").unwrap_or(DEFAULT_IDLE_TIMEOUT_MINS);
+
+        Self {
+            alone: Duration::from_secs(alone_secs),
+            idle: Duration::from_secs(idle_mins * 60),
+        }
+    }
+}
+
+fn env_number(key: &str) -> Option<u64> {
+    env::var(key).ok().and_then(|value| value.parse().ok())
+}
+
+pub struct DisconnectTimeoutsMap;
+
+impl serenity::prelude::TypeMapKey for DisconnectTimeoutsMap {
+    type Value = DisconnectTimeouts;
+}
+
+/// Pending disconnections of a guild. Each timer has a generation that changes whenever it is restarted or
+/// cancelled, so a timer that fires late can tell it's no longer wanted.
+#[derive(Default)]
+pub struct IdleState {
+    alone_generation: u64,
+    idle_generation: u64,
+    is_alone: bool,
+    /// Whether the track was paused by the bot when it was left alone, so it's only unpaused in that case.
+    paused_while_alone: bool,
+}
+
+impl IdleState {
+    fn generation_mut(&mut self, reason: DisconnectReason) -> &mut u64 {
+        match reason {
+            DisconnectReason::Alone => &mut self.alone_generation,
+            DisconnectReason::Idle => &mut self.idle_generation,
+        }
+    }
+
+    /// Invalidates the pending timer for the reason, returning the generation of the one replacing it.
+    fn restart(&mut self, reason: DisconnectReason) -> u64 {
+        let generation = self.generation_mut(reason);
+        *generation += 1;
+        *generation
+    }
+
+    fn cancel(&mut self, reason: DisconnectReason) {
+        *self.generation_mut(reason) += 1;
+    }
+
+    fn is_current(&self, reason: DisconnectReason, generation: u64) -> bool {
+        match reason {
+            DisconnectReason::Alone => self.alone_generation == generation,
+            DisconnectReason::Idle => self.idle_generation == generation,
+        }
+    }
+
+    /// Cancels all the timers, used once the bot has left the voice channel.
+    pub fn reset(&mut self) {
+        self.cancel(DisconnectReason::Alone);
+        self.cancel(DisconnectReason::Idle);
+        self.is_alone = false;
+        self.paused_while_alone = false;
+    }
+}
+
+/// Pauses the track when everyone has left the voice channel of the bot, and leaves if nobody comes back in time.
+/// The track is unpaused if someone joins again before.
+pub async fn check_alone(ctx: &Context, guild_id: &GuildId) {
+    if get_bot_voice_channel(ctx, guild_id).is_none() {
+        return;
+    }
+
+    let is_alone = get_voice_channel_listeners(ctx, guild_id).is_empty();
+
+    let generation = {
+        let data = &mut ctx.data.write().await;
+        let server = match get_server_mut(data, guild_id) {
+            Ok(server) => server,
+            Err(_) => return,
+        };
+
+        if is_alone == server.idle.is_alone {
+            return;
+        }
+
+        server.idle.is_alone = is_alone;
+
+        if !is_alone {
+            info!("Listeners are back in guild {}", guild_id.0);
+            server.idle.cancel(DisconnectReason::Alone);
+
+            if server.idle.paused_while_alone {
+                server.idle.paused_while_alone = false;
+
+                if let Some(Err(why)) = server.track_handle.as_ref().map(|track_handle| track_handle.play()) {
+                    info!("Unpausing failed: {why:?}");
+                }
+            }
+
+            return;
+        }
+
+        info!("Bot left alone in guild {}", guild_id.0);
+
+        if let Some(track_handle) = &server.track_handle {
+            let is_playing = matches!(track_handle.get_info().await, Ok(info) if info.playing == PlayMode::Play);
+
+            if is_playing {
+                server.idle.paused_while_alone = track_handle.pause().is_ok();
+            }
+        }
+
+        server.idle.restart(DisconnectReason::Alone)
+    };
+
+    schedule_disconnect(ctx, guild_id, DisconnectReason::Alone, generation).await;
+}
+
+/// Leaves the voice channel if nothing is played for a while.
+pub async fn start_idle_timer(ctx: &Context, guild_id: &GuildId) {
+    let generation = {
+        let data = &mut ctx.data.write().await;
+
+        match get_server_mut(data, guild_id) {
+            Ok(server) => server.idle.restart(DisconnectReason::Idle),
+            Err(_) => return,
+        }
+    };
+
+    schedule_disconnect(ctx, guild_id, DisconnectReason::Idle, generation).await;
+}
+
+async fn schedule_disconnect(ctx: &Context, guild_id: &GuildId, reason: DisconnectReason, generation: u64) {
+    let timeout = {
+        let data = ctx.data.read().await;
+        let timeouts = match data.get::<DisconnectTimeoutsMap>() {
+            Some(timeouts) => timeouts,
+            None => return,
+        };
+
+        match reason {
+            DisconnectReason::Alone => timeouts.alone,
+            DisconnectReason::Idle => timeouts.idle,
+        }
+    };
+
+    let ctx = ctx.clone();
+    let guild_id = *guild_id;
+
+    tokio::spawn(async move {
+        tokio::time::sleep(timeout).await;
+
+        let channel_id = {
+            let data = ctx.data.read().await;
+            let server = match get_server(&data, &guild_id) {
+                Some(server) => server,
+                None => return,
+            };
+
+            let still_applies = match reason {
+                DisconnectReason::Alone => server.idle.is_alone,
+                DisconnectReason::Idle => server.track_handle.is_none() && server.queue.is_empty(),
+            };
+
+            if !server.idle.is_current(reason, generation) || !still_applies {
+                return;
+            }
+
+            server.channel_id
+        };
+
+        let manager = songbird::get(&ctx).await
+            .expect("Songbird Voice client placed in at initialisation.").clone();
+
+        if manager.get(guild_id).is_none() {
+            return;
+        }
+
+        info!("Leaving voice channel of guild {}", guild_id.0);
+
+        if let Err(why) = manager.remove(guild_id).await {
+            info!("Leaving voice channel failed: {why:?}");
+
+            return;
+        }
+
+        if let Some(channel_id) = channel_id {
+            check_msg(channel_id.say(&ctx.http, reason.message()).await);
+        }
+    });
+}
@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serenity::builder::{CreateComponents, CreateEmbed};
+use serenity::client::Context;
+use serenity::framework::standard::CommandResult;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::channel::Message;
+use serenity::model::channel::ReactionType::Unicode;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::Result as SerenityResult;
+use tracing::info;
+
+use crate::errors::DubaError;
+use crate::models::Requester;
+
+const LOADING_EMOJI: &str = "⏳";
+
+enum Source<'a> {
+    Message(&'a Message),
+    /// Slash commands are deferred before running, so every answer is sent as a followup message.
+    Slash(&'a ApplicationCommandInteraction),
+}
+
+/// A command being run, either from a prefixed message or from a slash command, so both interfaces share the same
+/// handlers.
+pub struct Invocation<'a> {
+    source: Source<'a>,
+    answered: AtomicBool,
+}
+
+impl<'a> Invocation<'a> {
+    pub fn from_message(msg: &'a Message) -> Self {
+        Self {
+            source: Source::Message(msg),
+            answered: AtomicBool::new(false),
+        }
+    }
+
+    pub fn from_slash(command: &'a ApplicationCommandInteraction) -> Self {
+        Self {
+            source: Source::Slash(command),
+            answered: AtomicBool::new(false),
+        }
+    }
+
+    pub fn guild_id(&self) -> CommandResult<GuildId> {
+        let guild_id = match self.source {
+            Source::Message(msg) => msg.guild_id,
+            Source::Slash(command) => command.guild_id,
+        };
+
+        Ok(guild_id.ok_or(DubaError::GuildNotFound)?)
+    }
+
+    pub fn channel_id(&self) -> ChannelId {
+        match self.source {
+            Source::Message(msg) => msg.channel_id,
+            Source::Slash(command) => command.channel_id,
+        }
+    }
+
+    pub fn author_id(&self) -> UserId {
+        match self.source {
+            Source::Message(msg) => msg.author.id,
+            Source::Slash(command) => command.user.id,
+        }
+    }
+
+    /// The author of the command, named as they appear in the server.
+    pub fn requester(&self) -> Requester {
+        let (user, member_nick) = match self.source {
+            Source::Message(msg) => (&msg.author, msg.member.as_ref().and_then(|member| member.nick.clone())),
+            Source::Slash(command) => (&command.user, command.member.as_ref().and_then(|member| member.nick.clone())),
+        };
+
+        Requester {
+            user_id: user.id,
+            display_name: member_nick.unwrap_or(user.name.clone()),
+        }
+    }
+
+    pub async fn say(&self, ctx: &Context, content: impl ToString) -> SerenityResult<Message> {
+        let content = content.to_string();
+
+        match self.source {
+            Source::Message(msg) => msg.channel_id.say(&ctx.http, content).await,
+            Source::Slash(command) => {
+                self.answered.store(true, Ordering::Relaxed);
+                command.create_followup_message(&ctx.http, |f| f.content(content)).await
+            }
+        }
+    }
+
+    pub async fn reply(&self, ctx: &Context, content: impl ToString) -> SerenityResult<Message> {
+        let content = content.to_string();
+
+        match self.source {
+            Source::Message(msg) => msg.reply(ctx, content).await,
+            Source::Slash(_) => self.say(ctx, content).await,
+        }
+    }
+
+    pub async fn send_embed(&self, ctx: &Context, embed: CreateEmbed, components: Option<CreateComponents>) -> SerenityResult<Message> {
+        match self.source {
+            Source::Message(msg) => {
+                msg.channel_id.send_message(&ctx.http, |m| {
+                    m.set_embed(embed);
+
+                    if let Some(components) = components {
+                        m.set_components(components);
+                    }
+
+                    m
+                }).await
+            }
+            Source::Slash(command) => {
+                self.answered.store(true, Ordering::Relaxed);
+                command.create_followup_message(&ctx.http, |f| {
+                    f.add_embed(embed);
+
+                    if let Some(components) = components {
+                        f.set_components(components);
+                    }
+
+                    f
+                }).await
+            }
+        }
+    }
+
+    /// Reacts to the message of prefixed commands. Slash commands can't be reacted to, so the emoji is sent instead.
+    pub async fn react(&self, ctx: &Context, emoji: &str) -> SerenityResult<()> {
+        match self.source {
+            Source::Message(msg) => msg.react(&ctx.http, Unicode(emoji.to_string())).await.map(|_| ()),
+            Source::Slash(_) => self.say(ctx, emoji).await.map(|_| ()),
+        }
+    }
+
+    /// Shows that a slow command is running. Deferred slash commands already show it on their own.
+    pub async fn show_loading(&self, ctx: &Context) -> SerenityResult<()> {
+        match self.source {
+            Source::Message(msg) => msg.react(&ctx.http, Unicode(LOADING_EMOJI.to_string())).await.map(|_| ()),
+            Source::Slash(_) => Ok(()),
+        }
+    }
+
+    pub async fn hide_loading(&self, ctx: &Context) -> SerenityResult<()> {
+        match self.source {
+            // Without a user, the reaction of the bot itself is deleted
+            Source::Message(msg) => msg.channel_id.delete_reaction(&ctx.http, msg.id, None, Unicode(LOADING_EMOJI.to_string())).await,
+            Source::Slash(_) => Ok(()),
+        }
+    }
+
+    /// Explains why the command failed, if it did. Slash commands always get an answer, otherwise Discord keeps
+    /// showing them as loading.
+    pub async fn finish(&self, ctx: &Context, result: &CommandResult) {
+        if let Err(why) = result {
+            match why.downcast_ref::<DubaError>() {
+                Some(error) => {
+                    let guild_id = self.guild_id().ok().map(|guild_id| guild_id.0);
+                    info!(kind = error.kind(), guild_id, user_id = self.author_id().0, "Command failed: {error}");
+
+                    if let Err(why) = self.say(ctx, error.user_message()).await {
+                        info!("Error sending message: {why:?}");
+                    }
+                }
+                None => info!("Command failed: {why:?}"),
+            }
+        }
+
+        if matches!(self.source, Source::Slash(_)) && !self.answered.load(Ordering::Relaxed) {
+            let emoji = if result.is_ok() { "👍" } else { "💀" };
+
+            if let Err(why) = self.react(ctx, emoji).await {
+                info!("Error sending message: {why:?}");
+            }
+        }
+    }
+}
@@ -1,17 +1,18 @@
-use std::cmp::min;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::env;
+use std::mem;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use dotenvy::dotenv;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
 use serenity::{
     async_trait,
     client::{Client, EventHandler},
     framework::{
         standard::{
-            Args, CommandResult,
-            macros::{command, group},
+            Args, CommandResult, DispatchError, Reason,
+            macros::{command, group, hook},
         },
         StandardFramework,
     },
@@ -19,28 +20,79 @@ use serenity::{
     prelude::GatewayIntents,
     Result as SerenityResult,
 };
+use serenity::builder::{CreateComponents, CreateEmbed};
 use serenity::client::Context;
 use serenity::framework::standard::CommandError;
-use serenity::model::channel::ReactionType::Unicode;
+use serenity::model::application::interaction::{Interaction, InteractionResponseType};
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
 use serenity::model::guild::Guild;
-use serenity::model::id::{ChannelId, UserId};
+use serenity::model::id::{ChannelId, RoleId, UserId};
 use serenity::model::prelude::{GuildId, VoiceState};
 use serenity::prelude::TypeMap;
-use songbird::{Event, EventContext, EventHandler as VoiceEventHandler, SerenityInit, ytdl};
-use songbird::input::ytdl_search;
+use songbird::{Event, EventContext, EventHandler as VoiceEventHandler, SerenityInit};
+use songbird::input::restartable::Restartable;
 use songbird::TrackEvent::End;
 use songbird::tracks::TrackHandle;
 use tokio::sync::{RwLockReadGuard, RwLockWriteGuard};
 use tracing::info;
 
-use crate::models::{DubaServers, ServerData, Song};
-use crate::playlists::songs_list_from_playlist_url;
+use crate::embeds::{
+    history_embed, now_playing_embed, QUEUE_PAGE_BUTTON_PREFIX, queue_embed, queue_page_buttons, queue_pages_count,
+    saved_playlists_embed, search_results_embed, search_results_menu,
+};
+use crate::errors::DubaError;
+use crate::idle::{check_alone, DisconnectTimeouts, DisconnectTimeoutsMap, start_idle_timer};
+use crate::invocation::Invocation;
+use crate::models::{DEFAULT_MAX_PLAYLIST_SONGS, DEFAULT_VOLUME, DubaServers, MAX_VOLUME, ServerData, Song};
+use crate::permissions::{DJ_CHECK, is_dj, NOT_A_DJ_MESSAGE};
+use crate::persistence::{Persistence, PersistenceMap, save_servers};
+use crate::play_request::PlayRequest;
+use crate::playlists::{PlaylistOptions, SkipReason};
+use crate::queue::{GuildQueue, QueuePosition, RepeatMode};
+use crate::resolver::{PlaylistReader, resolve_songs, ResolverMap, TrackResolver, YtDlpResolver};
+use crate::saved_playlists::{
+    PlaylistAction, PlaylistOwner, PlaylistRequest, PlaylistsFile, PlaylistsFileMap, save_playlists, SavedPlaylists,
+    SavedPlaylistsMap,
+};
+use crate::slash::{autocomplete_queue_index, register_commands, run_slash_command};
+use crate::timestamps::SeekTarget;
+use crate::urls::{classify, start_time_from_url, UrlKind};
+use crate::votes::{parse_skip_threshold, required_votes};
 
+mod play_request;
 mod playlists;
 mod models;
+mod embeds;
+mod errors;
+mod history;
+mod idle;
+mod invocation;
+mod permissions;
+mod persistence;
+mod queue;
+mod resolver;
+mod saved_playlists;
+mod slash;
+mod timestamps;
+mod urls;
+mod votes;
 
 struct Handler;
 
+/// How many songs of a playlist are queued between each update of the progress message.
+const PLAYLIST_PROGRESS_STEP: usize = 25;
+/// Times a song is tried before skipping it.
+const MAX_ATTEMPTS_PER_SONG: usize = 2;
+/// Songs failing in a row before slowing down the retries, and before giving up on the queue.
+const BACKOFF_AFTER_FAILURES: usize = 3;
+const MAX_CONSECUTIVE_FAILURES: usize = 8;
+const RETRY_INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const RETRY_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const SEARCH_RESULTS_COUNT: usize = 5;
+/// How long the search command waits for the user to pick one of the results.
+const SEARCH_PICK_TIMEOUT: Duration = Duration::from_secs(60);
+const NO_HISTORY_MESSAGE: &str = "No track has been played yet";
+
 pub struct ServersManager;
 
 impl serenity::prelude::TypeMapKey for ServersManager {
@@ -57,8 +109,6 @@ impl serenity::prelude::TypeMapKey for BotDataMap {
     type Value = BotData;
 }
 
-const UNKNOWN_TRACK_TITLE: &str = "UNKNOWN TRACK";
-
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
@@ -79,8 +129,64 @@ impl EventHandler for Handler {
         info!("{} is connected!", ready.user.name);
 
         let bot_data = BotData { id: ready.user.id.0 };
-        let data = &mut ctx.data.write().await;
-        data.insert::<BotDataMap>(bot_data);
+        let resume_offers: Vec<(ChannelId, usize)>;
+
+        {
+            let data = &mut ctx.data.write().await;
+
+            // Ready is fired again after each reconnection, when the bot data is already there. Commands are
+            // registered and the queues offered to resume only once.
+            if data.contains_key::<BotDataMap>() {
+                return;
+            }
+
+            data.insert::<BotDataMap>(bot_data);
+
+            resume_offers = data.get::<ServersManager>()
+                .map(|duba_servers| {
+                    duba_servers.resumable
+                        .values()
+                        .filter_map(|snapshot| snapshot.channel_id.map(|channel_id| (channel_id, snapshot.len())))
+                        .collect()
+                })
+                .unwrap_or_default();
+        }
+
+        if let Err(why) = register_commands(&ctx).await {
+            info!("Error registering slash commands: {why:?}");
+        }
+
+        for (channel_id, songs_count) in resume_offers {
+            check_msg(
+                channel_id.say(
+                    &ctx.http,
+                    format!("I was restarted with {songs_count} songs pending. Use `!resume` to continue where it left off."),
+                ).await
+            );
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::ApplicationCommand(command) => {
+                if let Err(why) = run_slash_command(&ctx, &command).await {
+                    info!("Error answering slash command: {why:?}");
+                }
+            }
+            Interaction::Autocomplete(autocomplete) => {
+                if let Err(why) = autocomplete_queue_index(&ctx, &autocomplete).await {
+                    info!("Error answering autocomplete: {why:?}");
+                }
+            }
+            Interaction::MessageComponent(component) => {
+                if let Some(page) = component.data.custom_id.strip_prefix(QUEUE_PAGE_BUTTON_PREFIX) {
+                    if let Err(error) = update_queue_page(&ctx, &component, page).await {
+                        info!("{:#?}", error)
+                    }
+                }
+            }
+            _ => {}
+        }
     }
 
     async fn voice_state_update(&self, ctx: Context, _: Option<VoiceState>, new: VoiceState) {
@@ -103,16 +209,22 @@ impl EventHandler for Handler {
                     if let Err(error) = stop_current_track(&ctx, &guild_id, None).await {
                         info!("{:#?}", error)
                     }
+
+                    reset_idle_state(&ctx, &guild_id).await;
                 } else {
                     info!("Bot ID does not match disconnected user");
                 }
             }
         }
+
+        if let Some(guild_id) = new.guild_id {
+            check_alone(&ctx, &guild_id).await;
+        }
     }
 }
 
 #[group]
-#[commands(play, pause, unpause, next, stop, queue, shuffle, goto, pn, help)] // TODO add Shuffle and Help commands
+#[commands(play, search, pause, unpause, next, stop, queue, np, shuffle, goto, pn, insert, remove, move_song, swap, resume, loop_mode, seek, volume, vote_skip, dj_role, max_songs, previous, history, replay, playlist, help)] // TODO add Shuffle and Help commands
 struct General;
 
 #[tokio::main]
@@ -129,6 +241,8 @@ async fn main() {
         .configure(|c| {
             c.prefix("!")
         })
+        .after(after_command)
+        .on_dispatch_error(dispatch_error)
         .group(&GENERAL_GROUP);
 
     let intents = GatewayIntents::non_privileged()
@@ -144,13 +258,33 @@ async fn main() {
     {
         let mut w = client.data.write().await;
 
-        let duba_servers = DubaServers {
-            servers: HashMap::new()
+        let persistence = Persistence::from_env();
+        let mut duba_servers = DubaServers {
+            servers: HashMap::new(),
+            resumable: HashMap::new(),
         };
 
+        // Settings are restored right away, songs wait until someone resumes them
+        for (guild_id, snapshot) in persistence.load() {
+            duba_servers.servers.insert(guild_id, ServerData::with_settings_of(&snapshot));
+
+            if !snapshot.is_empty() {
+                duba_servers.resumable.insert(guild_id, snapshot);
+            }
+        }
+
         w.insert::<ServersManager>(duba_servers);
+        w.insert::<ResolverMap>(Arc::new(YtDlpResolver));
+        w.insert::<PersistenceMap>(Arc::new(persistence));
+
+        let playlists_file = PlaylistsFile::from_env();
+        w.insert::<SavedPlaylistsMap>(playlists_file.load());
+        w.insert::<PlaylistsFileMap>(Arc::new(playlists_file));
+        w.insert::<DisconnectTimeoutsMap>(DisconnectTimeouts::from_env());
     }
 
+    let data = client.data.clone();
+
     tokio::spawn(async move {
         let _ = client.start().await.map_err(|why| info!("Client ended: {why:?}"));
     });
@@ -158,60 +292,202 @@ async fn main() {
     tokio::signal::ctrl_c().await.expect("Control-C interruption failed!");
 
     info!("Received Ctrl-C, shutting down.");
+
+    save_servers(&data).await;
+}
+
+#[hook]
+async fn after_command(ctx: &Context, msg: &Message, _command_name: &str, result: CommandResult) {
+    Invocation::from_message(msg).finish(ctx, &result).await;
+}
+
+/// Tells the user why a command could not be run.
+#[hook]
+async fn dispatch_error(ctx: &Context, msg: &Message, error: DispatchError, command_name: &str) {
+    let message = match error {
+        DispatchError::CheckFailed(_, Reason::User(reason)) => reason,
+        DispatchError::LackingPermissions(permissions) => format!("You need the {permissions} permission to use {command_name}"),
+        error => {
+            info!("Command {command_name} not dispatched: {error:?}");
+
+            return;
+        }
+    };
+
+    check_msg(msg.channel_id.say(&ctx.http, message).await);
 }
 
 #[command]
 #[only_in(guilds)]
 async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    play_song_with_reaction(ctx, msg, args, true).await
+    play_song_with_reaction(ctx, &Invocation::from_message(msg), &PlayRequest::parse(args.message())?, QueuePosition::Back).await
 }
 
 #[command]
 #[only_in(guilds)]
 async fn pn(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    play_song_with_reaction(ctx, msg, args, false).await
+    play_song_with_reaction(ctx, &Invocation::from_message(msg), &PlayRequest::parse(args.message())?, QueuePosition::Front).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn search(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    search_command(ctx, &Invocation::from_message(msg), args.message()).await
+}
+
+/// Shows the first results for the given terms and queues the one picked by the user.
+async fn search_command(ctx: &Context, invocation: &Invocation<'_>, query: &str) -> CommandResult {
+    let query = query.trim();
+
+    if query.is_empty() {
+        check_msg(invocation.say(ctx, "Missing search terms. Use `!search [Title]`.").await);
+
+        return Ok(());
+    }
+
+    let resolver = get_resolver(ctx).await?;
+
+    invocation.show_loading(ctx).await?;
+    let search_results = resolver.search_results(query, SEARCH_RESULTS_COUNT).await;
+    invocation.hide_loading(ctx).await?;
+
+    let songs = search_results?;
+
+    if songs.is_empty() {
+        check_msg(invocation.say(ctx, format!("No results found for {query}")).await);
+
+        return Ok(());
+    }
+
+    let mut components = CreateComponents::default();
+    search_results_menu(&mut components, &songs);
+
+    let mut results_message = invocation.send_embed(ctx, search_results_embed(&songs), Some(components)).await?;
+
+    let interaction = results_message.await_component_interaction(ctx)
+        .author_id(invocation.author_id())
+        .timeout(SEARCH_PICK_TIMEOUT)
+        .await;
+
+    let interaction = match interaction {
+        Some(interaction) => interaction,
+        None => {
+            results_message.edit(&ctx.http, |m| {
+                m.content("Search timed out, no song was picked")
+                    .set_embeds(Vec::new())
+                    .components(|c| c)
+            }).await?;
+
+            return Ok(());
+        }
+    };
+
+    let song = interaction.data.values
+        .first()
+        .and_then(|value| value.parse::<usize>().ok())
+        .and_then(|index| songs.get(index))
+        .cloned()
+        .ok_or(DubaError::InvalidSearchResult)?
+        .requested_by(invocation.requester());
+
+    interaction.create_interaction_response(&ctx.http, |r| {
+        r.kind(InteractionResponseType::UpdateMessage)
+            .interaction_response_data(|d| {
+                d.content(format!("Picked **{}**", song.title))
+                    .set_embeds(Vec::new())
+                    .components(|c| c)
+            })
+    }).await?;
+
+    join(ctx, invocation).await?;
+    deafen(ctx, invocation).await?;
+
+    let guild_id = invocation.guild_id()?;
+
+    push_song_to_guild(ctx, &guild_id, song, QueuePosition::Back).await?;
+    play_next_if_queue_empty(ctx, &guild_id, &invocation.channel_id()).await;
+    save_servers(&ctx.data).await;
+
+    invocation.react(ctx, "👍").await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn insert(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let index = args.single::<usize>().ok();
+
+    insert_command(ctx, &Invocation::from_message(msg), index, &PlayRequest::parse(args.rest())?).await
+}
+
+async fn insert_command(ctx: &Context, invocation: &Invocation<'_>, index: Option<usize>, request: &PlayRequest) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+    let queue_len = get_queue_len(ctx, &guild_id).await;
+
+    match index {
+        Some(index) if index >= 1 && index <= queue_len + 1 => {
+            play_song_with_reaction(ctx, invocation, request, QueuePosition::At(index)).await
+        }
+        _ => {
+            check_msg(invocation.say(ctx, format!("Invalid position. Use a position between 1 and {}.", queue_len + 1)).await);
+
+            Ok(())
+        }
+    }
 }
 
 #[command]
 #[only_in(guilds)]
 async fn help(ctx: &Context, msg: &Message) -> CommandResult {
+    help_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn help_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
     let message = r#"
-**Commands:**
-    **play [URL|Title]** - Plays (or adds to the queue) new tracks given a URL or a video title (supports youtube playlists).
+**Commands:** (also available as slash commands)
+    **play [URL|Title] [--playlist]** - Plays (or adds to the queue) new tracks given a URL or a video title (supports playlists and timestamped links). Videos opened from a playlist are queued alone unless `--playlist` is given.
+        Playlists accept `--from N`, `--to N`, `--limit N`, `--shuffle` and `--reverse`, e.g. `!play [URL] --from 10 --to 40 --shuffle`.
     **pause** - Pauses the current track.
     **unpause** - Unpauses the currently paused track.
     **stop** - Stops the current song and clears the queue.
-    **pn [URL|Title]** - Adds track to the top of the queue to be played next.
-    **next** - Plays next track.
+    **search [Title]** - Lists the first results for a title and lets you pick the one to queue.
+    **pn [URL|Title]** - Adds track to the top of the queue to be played next. Playlists are added in order, before the rest of the queue.
+    **next** - Plays next track, or votes to skip the current one if vote skipping is enabled.
+    **previous** - Plays again the previous track, the current one is played right after it (also `back`).
+    **replay** - Restarts the current track from the beginning.
+    **seek [mm:ss|+SECONDS|-SECONDS]** - Jumps to a position of the current track, or forwards/backwards from the current one.
     **queue** - Shows the queue of tracks.
+    **np** - Shows the current track, its progress and the next one.
+    **history** - Shows the last played tracks and who requested them.
+    **insert [INDEX] [URL|Title]** - Adds track to the queue at the given position.
+    **remove [INDEX|FROM-TO]** - Removes a track, or a range of tracks, from the queue.
+    **move [FROM] [TO]** - Moves a track of the queue to another position.
+    **swap [INDEX] [INDEX]** - Swaps the positions of two tracks of the queue.
     **goto [INDEX]** - Plays immediately the specific track of the queue (discards all previous tracks).
     **shuffle** - Reorders the queue randomly.
+    **volume [0-200]** - Sets the volume of the tracks, or shows it if no value is given.
+    **voteskip [on|off|PERCENTAGE]** - Requires votes of a percentage of the listeners to skip a track (50% by default), or lets anyone skip if off (requires Manage Server).
+    **loop** - Cycles the repeat mode between off, current track and whole queue.
+    **djrole [@ROLE|off]** - Restricts stop, shuffle, goto, previous, remove, move and swap to a role, admins and users alone with the bot (requires Manage Server).
+    **maxsongs [NUMBER]** - Sets how many songs a single request can add to the queue, or shows it if no value is given (requires Manage Server).
+    **playlist save|load|delete [NAME] [--mine]** - Saves the current track and the queue as a named playlist, queues a saved playlist, or deletes it. Playlists belong to the server, or to you with `--mine`.
+    **playlist add [NAME] [URL|Title] [--mine]** - Adds a track, or all the tracks of a playlist URL, to a saved playlist.
+    **playlist list** - Lists the saved playlists of the server and yours.
+    **resume** - Queues again the tracks that were pending when the bot was restarted.
     "#;
 
-    check_msg(msg.channel_id.say(&ctx.http, message).await);
+    check_msg(invocation.say(ctx, message).await);
 
     Ok(())
 }
 
-async fn play_song_with_reaction(ctx: &Context, msg: &Message, args: Args, insert_last: bool) -> CommandResult {
-    let bot_id: Option<u64>;
-
-    {
-        let data = ctx.data.read().await;
-        bot_id = data.get::<BotDataMap>().map(|data| data.id);
-    }
-
-    let loading_emoji = Unicode("⏳".to_string());
-
-    msg.react(&ctx.http, loading_emoji.clone()).await?;
+async fn play_song_with_reaction(ctx: &Context, invocation: &Invocation<'_>, request: &PlayRequest, position: QueuePosition) -> CommandResult {
+    invocation.show_loading(ctx).await?;
 
-    let play_song_result = play_song(ctx, msg, args, insert_last).await;
+    let play_song_result = play_song(ctx, invocation, request, position).await;
 
-    msg.react(&ctx.http, loading_emoji.clone()).await?;
-
-    if let Some(bot_id) = bot_id {
-        msg.channel_id.delete_reaction(&ctx.http, msg.id, Some(UserId(bot_id)), loading_emoji.clone()).await?;
-    }
+    invocation.hide_loading(ctx).await?;
 
     let answer_emoji = match play_song_result {
         Ok(_) => {
@@ -222,52 +498,171 @@ async fn play_song_with_reaction(ctx: &Context, msg: &Message, args: Args, inser
         }
     };
 
-    msg.react(&ctx.http, Unicode(answer_emoji.to_string())).await?;
+    invocation.react(ctx, answer_emoji).await?;
+
+    play_song_result
+}
+
+async fn play_song(ctx: &Context, invocation: &Invocation<'_>, request: &PlayRequest, position: QueuePosition) -> CommandResult {
+    join(ctx, invocation).await?;
+    deafen(ctx, invocation).await?;
+
+    let user_input = request.query.as_str();
+    let url_kind = classify(user_input);
+
+    info!("User input is {user_input}, classified as {url_kind:?}");
+
+    let guild_id = invocation.guild_id()?;
+    let resolver = get_resolver(ctx).await?;
+
+    match url_kind {
+        UrlKind::Search => {
+            let song = resolver.search(user_input).await?.requested_by(invocation.requester());
+            push_song_to_guild(ctx, &guild_id, song, position).await?;
+        }
+        UrlKind::Video => queue_video(ctx, invocation, &guild_id, resolver.as_ref(), user_input, position).await?,
+        UrlKind::Playlist => {
+            queue_playlist(ctx, invocation, &guild_id, resolver.as_ref(), user_input, &request.playlist, position).await?
+        }
+        UrlKind::VideoInPlaylist { playlist_url, .. } if request.whole_playlist => {
+            queue_playlist(ctx, invocation, &guild_id, resolver.as_ref(), &playlist_url, &request.playlist, position).await?
+        }
+        UrlKind::Mix { .. } if request.whole_playlist => {
+            queue_playlist(ctx, invocation, &guild_id, resolver.as_ref(), user_input, &request.playlist, position).await?
+        }
+        UrlKind::VideoInPlaylist { video_url, .. } | UrlKind::Mix { video_url } => {
+            check_msg(invocation.say(ctx, "Queuing only this video, add `--playlist` to queue its whole playlist").await);
+
+            queue_video(ctx, invocation, &guild_id, resolver.as_ref(), &video_url, position).await?
+        }
+    }
+
+    play_next_if_queue_empty(ctx, &guild_id, &invocation.channel_id()).await;
+    save_servers(&ctx.data).await;
 
     Ok(())
 }
 
-async fn play_song(ctx: &Context, msg: &Message, args: Args, insert_last: bool) -> CommandResult {
-    join(ctx, msg).await?;
-    deafen(ctx, msg).await?;
+async fn queue_video(
+    ctx: &Context,
+    invocation: &Invocation<'_>,
+    guild_id: &GuildId,
+    resolver: &dyn TrackResolver,
+    url: &str,
+    position: QueuePosition,
+) -> CommandResult {
+    let mut song = resolver.resolve_url(url).await?;
+    song.start = start_time_from_url(url);
+
+    push_song_to_guild(ctx, guild_id, song.requested_by(invocation.requester()), position).await?;
+
+    Ok(())
+}
 
-    let user_input = args.message();
+async fn queue_playlist(
+    ctx: &Context,
+    invocation: &Invocation<'_>,
+    guild_id: &GuildId,
+    resolver: &dyn TrackResolver,
+    url: &str,
+    options: &PlaylistOptions,
+    position: QueuePosition,
+) -> CommandResult {
+    let server_max_songs = {
+        let data = ctx.data.read().await;
+        get_server(&data, guild_id).map(|server| server.max_playlist_songs).unwrap_or(DEFAULT_MAX_PLAYLIST_SONGS)
+    };
+    let mut playlist = PlaylistReader::open(resolver, url, options, server_max_songs).await?;
+    let mut progress_message = invocation.say(ctx, "Loading playlist...").await?;
+    let mut queued_songs = 0;
 
-    info!("User input is {user_input}");
+    // Songs added to the back can be played while the rest is loading. Anywhere else, the queue could change in
+    // the meantime and split the playlist, so it's inserted at once when fully loaded.
+    let is_streamed = matches!(position, QueuePosition::Back);
+    let mut pending_songs = Vec::new();
 
-    let guild_id = get_guild_id(ctx, msg)?;
+    let requester = invocation.requester();
 
-    if user_input.starts_with("http") && user_input.contains("&list=") || user_input.contains("?list=") {
-        info!("Detected playlist in {user_input}");
+    while let Some(song) = playlist.next_song().await {
+        pending_songs.push(song.requested_by(requester.clone()));
+        queued_songs += 1;
 
-        let songs = songs_list_from_playlist_url(&user_input)?;
-        push_songs_list_to_server(ctx, &guild_id, songs).await?;
-    } else {
-        let input = if user_input.starts_with("http") {
-            ytdl(user_input).await
+        if is_streamed {
+            push_songs_to_guild(ctx, guild_id, mem::take(&mut pending_songs), position).await?;
+
+            if queued_songs == 1 {
+                // Start playing as soon as possible instead of waiting for the whole playlist to be read
+                play_next_if_queue_empty(ctx, guild_id, &invocation.channel_id()).await;
+            }
+        }
+
+        if queued_songs % PLAYLIST_PROGRESS_STEP == 0 {
+            let progress = if is_streamed { "queued" } else { "loaded" };
+            edit_message(ctx, &mut progress_message, format!("Loading playlist... {queued_songs} songs {progress}")).await;
+        }
+    }
+
+    if !pending_songs.is_empty() {
+        let data = &mut ctx.data.write().await;
+        let server = get_or_insert_server_mut(data, guild_id)?;
+
+        // Songs may have been removed while the playlist was loading, the checked index could be past the end now
+        let position = server.queue.position_within(position);
+        server.queue.insert_all(pending_songs, position);
+    }
+
+    let skipped_entries = playlist.skipped_entries().clone();
+    let reached_server_max = playlist.reached_server_max();
+
+    // Dropping the reader stops yt-dlp from listing the rest of the playlist
+    drop(playlist);
+
+    let skipped_summary = describe_skipped_entries(&skipped_entries);
+
+    if !skipped_entries.is_empty() {
+        info!("Skipped entries of playlist {url}: {skipped_entries:?}");
+    }
+
+    if queued_songs == 0 {
+        // The error is explained once the command is done, only the skipped entries are left to report
+        if skipped_entries.is_empty() {
+            if let Err(why) = progress_message.delete(&ctx.http).await {
+                info!("Error deleting message: {why:?}");
+            }
         } else {
-            ytdl_search(user_input).await
-        }.map_err(|_| CommandError::from(format!("Could not load song for input {user_input}")))?;
+            edit_message(ctx, &mut progress_message, format!("Skipped {skipped_summary}")).await;
+        }
 
-        let source_url = input.metadata.source_url.ok_or(CommandError::from(format!("Could not load song for input {user_input}")))?;
-        let song_name = input.metadata.title.unwrap_or(UNKNOWN_TRACK_TITLE.to_string());
-        let song_duration = input.metadata.duration;
+        return Err(DubaError::PlaylistEmpty { url: url.to_string() }.into());
+    }
 
-        let song = Song {
-            title: song_name,
-            url: source_url,
-            duration: song_duration,
-        };
+    let mut message = if skipped_entries.is_empty() {
+        format!("Queued {queued_songs} songs from the playlist")
+    } else {
+        format!("Queued {queued_songs} songs from the playlist, skipped {skipped_summary}")
+    };
 
-        push_song_to_guild(ctx, &guild_id, song, insert_last).await?;
+    if reached_server_max {
+        message.push_str(&format!(". The rest was left out, as this server allows {server_max_songs} songs per request"));
     }
 
-    play_next_if_queue_empty(ctx, &guild_id, msg).await;
+    edit_message(ctx, &mut progress_message, message).await;
 
     Ok(())
 }
 
-async fn play_next_if_queue_empty(ctx: &Context, guild_id: &GuildId, msg: &Message) {
+/// Describes how many entries of a playlist were skipped and why, e.g. `3 entries (2 private or deleted, 1 unreadable)`.
+fn describe_skipped_entries(skipped_entries: &HashMap<SkipReason, usize>) -> String {
+    let total: usize = skipped_entries.values().sum();
+    let mut reasons: Vec<(&SkipReason, &usize)> = skipped_entries.iter().collect();
+    reasons.sort_by(|a, b| b.1.cmp(a.1));
+
+    let reasons: Vec<String> = reasons.iter().map(|(reason, count)| format!("{count} {reason}")).collect();
+
+    format!("{total} entries ({})", reasons.join(", "))
+}
+
+async fn play_next_if_queue_empty(ctx: &Context, guild_id: &GuildId, channel_id: &ChannelId) {
     info!("play_next_if_queue_empty start");
     let is_not_playing: bool;
     let queue_is_empty: bool;
@@ -276,15 +671,13 @@ async fn play_next_if_queue_empty(ctx: &Context, guild_id: &GuildId, msg: &Messa
         let data = ctx.data.read().await;
         let songs = get_songs_from_guild(&data, guild_id).await;
 
-        let track_handle: Option<&TrackHandle> = get_track_handle(&data, guild_id).await;
-        is_not_playing = track_handle.is_none();
-        queue_is_empty = songs.is_empty()
+        // A song being started isn't playing yet, but starting another one would stop it
+        is_not_playing = !get_server(&data, guild_id).map(|server| server.is_playing_or_starting()).unwrap_or(false);
+        queue_is_empty = songs.map(|songs| songs.is_empty()).unwrap_or(true)
     }
 
     if !queue_is_empty && is_not_playing {
-        while play_next_song(ctx, guild_id, &msg.channel_id).await.is_err() {
-            info!("Next song failed")
-        }
+        play_next_available_song(ctx, guild_id, channel_id).await;
     }
 
     info!("play_next_if_queue_empty end");
@@ -293,12 +686,16 @@ async fn play_next_if_queue_empty(ctx: &Context, guild_id: &GuildId, msg: &Messa
 #[command]
 #[only_in(guilds)]
 async fn pause(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild_id = get_guild_id(ctx, msg)?;
+    pause_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn pause_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
     let data = ctx.data.read().await;
 
     match get_track_handle(&data, &guild_id).await {
         Some(track_handle) => track_handle.pause()?,
-        None => check_msg(msg.channel_id.say(&ctx.http, "o_O Already stopped").await),
+        None => check_msg(invocation.say(ctx, "o_O Already stopped").await),
     }
 
     Ok(())
@@ -306,21 +703,30 @@ async fn pause(ctx: &Context, msg: &Message) -> CommandResult {
 
 #[command]
 #[only_in(guilds)]
+#[checks(DJ)]
 async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild_id = get_guild_id(ctx, msg)?;
+    stop_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn stop_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
 
     clear_queue(ctx, &guild_id).await?;
-    stop_current_track(ctx, &guild_id, Some(&msg.channel_id)).await?;
-    leave(ctx, msg).await?;
+    stop_current_track(ctx, &guild_id, Some(&invocation.channel_id())).await?;
+    leave(ctx, invocation).await?;
 
     Ok(())
 }
 
 async fn clear_queue(ctx: &Context, guild_id: &GuildId) -> CommandResult {
-    let data = &mut ctx.data.write().await;
-    let server = get_server_mut(data, &guild_id)?;
+    {
+        let data = &mut ctx.data.write().await;
+        let server = get_server_mut(data, guild_id)?;
+
+        server.queue.clear();
+    }
 
-    server.queue.clear();
+    save_servers(&ctx.data).await;
 
     Ok(())
 }
@@ -328,25 +734,119 @@ async fn clear_queue(ctx: &Context, guild_id: &GuildId) -> CommandResult {
 #[command]
 #[only_in(guilds)]
 async fn queue(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild_id = get_guild_id(ctx, msg)?;
+    queue_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn queue_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    match build_queue_page(ctx, &guild_id, 0).await {
+        Some((embed, page, pages_count)) => {
+            let components = if pages_count > 1 {
+                let mut components = CreateComponents::default();
+                queue_page_buttons(&mut components, page, pages_count);
+                Some(components)
+            } else {
+                None
+            };
+
+            check_msg(invocation.send_embed(ctx, embed, components).await);
+        }
+        None => check_msg(invocation.say(ctx, "The queue is empty!").await),
+    }
+
+    Ok(())
+}
+
+/// Shows another page of the queue when one of its buttons is pressed.
+async fn update_queue_page(ctx: &Context, component: &MessageComponentInteraction, page: &str) -> CommandResult {
+    let guild_id = component.guild_id.ok_or(DubaError::GuildNotFound)?;
+    let page = page.parse::<usize>()?;
+
+    match build_queue_page(ctx, &guild_id, page).await {
+        Some((embed, page, pages_count)) => {
+            component.create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| {
+                        d.set_embed(embed)
+                            .components(|c| queue_page_buttons(c, page, pages_count))
+                    })
+            }).await?;
+        }
+        None => {
+            component.create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| {
+                        d.content("The queue is empty!")
+                            .set_embeds(Vec::new())
+                            .components(|c| c)
+                    })
+            }).await?;
+        }
+    }
 
+    Ok(())
+}
+
+/// Builds the requested page of the queue, returning it along with the page actually shown (it may have shrunk since
+/// the buttons were created) and the number of pages. Returns None if the queue is empty.
+async fn build_queue_page(ctx: &Context, guild_id: &GuildId, page: usize) -> Option<(CreateEmbed, usize, usize)> {
     let data = ctx.data.read().await;
-    let songs = get_songs_from_guild(&data, &guild_id).await;
+    let server = get_server(&data, guild_id)?;
 
-    if songs.is_empty() {
-        check_msg(msg.channel_id.say(&ctx.http, "The queue is empty!").await);
-    } else {
-        let max_songs = 20;
-        let mut songs_titles: Vec<String> = Vec::with_capacity(min(songs.len(), max_songs));
+    if server.queue.is_empty() {
+        return None;
+    }
+
+    let current_remaining = match &server.track_handle {
+        Some(track_handle) => {
+            let position = track_handle.get_info().await.ok().map(|info| info.position);
+            let duration = server.current_song.as_ref().and_then(|song| song.duration);
 
-        for (index, song) in songs.iter().take(max_songs).enumerate() {
-            let song_index = index + 1;
-            songs_titles.push(format!("{song_index} - {}", song.title));
+            position.zip(duration).map(|(position, duration)| duration.saturating_sub(position))
         }
+        None => Some(Duration::ZERO),
+    };
+
+    let pages_count = queue_pages_count(&server.queue);
+    let page = page.min(pages_count - 1);
+
+    Some((queue_embed(&server.queue, current_remaining, page), page, pages_count))
+}
 
-        let songs_formatted = songs_titles.join("\n");
+#[command]
+#[only_in(guilds)]
+#[aliases("nowplaying")]
+async fn np(ctx: &Context, msg: &Message) -> CommandResult {
+    np_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn np_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+    let current_song: Option<Song>;
+    let position: Option<Duration>;
+    let next_song: Option<Song>;
+
+    {
+        let data = ctx.data.read().await;
+        let server = get_server(&data, &guild_id);
+
+        current_song = server.and_then(|server| server.current_song.clone());
+        next_song = server.and_then(|server| server.queue.iter().next().cloned());
+        position = match server.and_then(|server| server.track_handle.as_ref()) {
+            Some(track_handle) => track_handle.get_info().await.ok().map(|info| info.position),
+            None => None,
+        };
+    }
 
-        check_msg(msg.channel_id.say(&ctx.http, format!("**Queue**:\n```{songs_formatted}```")).await);
+    match current_song {
+        Some(song) => {
+            let mut embed = CreateEmbed::default();
+            now_playing_embed(&mut embed, &song, position, next_song.as_ref());
+
+            check_msg(invocation.send_embed(ctx, embed, None).await);
+        }
+        None => check_msg(invocation.say(ctx, "Nothing is playing").await),
     }
 
     Ok(())
@@ -355,7 +855,11 @@ async fn queue(ctx: &Context, msg: &Message) -> CommandResult {
 #[command]
 #[only_in(guilds)]
 async fn unpause(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild_id = get_guild_id(ctx, msg)?;
+    unpause_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn unpause_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
 
     let data = ctx.data.read().await;
 
@@ -364,7 +868,7 @@ async fn unpause(ctx: &Context, msg: &Message) -> CommandResult {
             track_handle.play()?
         }
         None => {
-            check_msg(msg.channel_id.say(&ctx.http, "o_O Already stopped").await);
+            check_msg(invocation.say(ctx, "o_O Already stopped").await);
         }
     }
 
@@ -374,7 +878,11 @@ async fn unpause(ctx: &Context, msg: &Message) -> CommandResult {
 #[command]
 #[only_in(guilds)]
 async fn next(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild_id = get_guild_id(ctx, msg)?;
+    next_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn next_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
 
     info!("NEXT - Next command invoked from guild {}!", guild_id.0);
 
@@ -382,200 +890,1166 @@ async fn next(ctx: &Context, msg: &Message) -> CommandResult {
 
     {
         let data = ctx.data.read().await;
-        let songs_queue = get_songs_from_guild(&data, &guild_id).await;
+        let songs_queue = get_songs_from_guild(&data, &guild_id).await?;
         info!("NEXT - There are {} songs in the queue!", songs_queue.len());
 
         is_queue_empty = songs_queue.is_empty();
     }
 
-    if !is_queue_empty {
+    if !is_queue_empty && has_enough_skip_votes(ctx, invocation, &guild_id).await? {
         info!("NEXT - Stopping current song");
         // Stopping the current song will automatically start the next one
-        stop_current_track(ctx, &guild_id, Some(&msg.channel_id)).await?;
+        stop_current_track(ctx, &guild_id, Some(&invocation.channel_id())).await?;
     }
 
     Ok(())
 }
 
-#[command]
-#[only_in(guilds)]
-async fn shuffle(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild_id = get_guild_id(ctx, msg)?;
+/// Registers the vote of the user if vote skipping is enabled, returning whether the current track can be skipped.
+/// The user who requested the track can always skip it.
+async fn has_enough_skip_votes(ctx: &Context, invocation: &Invocation<'_>, guild_id: &GuildId) -> CommandResult<bool> {
+    let user_id = invocation.author_id();
+    let listeners = get_voice_channel_listeners(ctx, guild_id);
 
-    info!("Shuffle - Next command invoked from guild {}!", guild_id.0);
-    let data = &mut ctx.data.write().await;
-    let server = get_server_mut(data, &guild_id)?;
+    let tally = {
+        let data = &mut ctx.data.write().await;
+        let server = get_server_mut(data, guild_id)?;
 
-    let songs = &mut server.queue;
-    songs.make_contiguous().shuffle(&mut thread_rng());
+        let threshold = match server.skip_threshold {
+            Some(threshold) => threshold,
+            None => return Ok(true),
+        };
 
-    msg.react(&ctx.http, Unicode("👍".to_string())).await?;
-    
-    Ok(())
-}
+        let is_requester = server.current_song
+            .as_ref()
+            .and_then(|song| song.requester.as_ref())
+            .map(|requester| requester.user_id == user_id)
+            .unwrap_or(false);
 
-#[command]
-#[only_in(guilds)]
-async fn goto(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    let guild_id = get_guild_id(ctx, msg)?;
+        if is_requester {
+            return Ok(true);
+        }
 
-    let index = match args.single::<usize>() {
-        Ok(url) => url,
-        Err(_) => {
-            check_msg(msg.channel_id.say(&ctx.http, "Invalid song index. Check the queue to list the songs.").await);
+        // Only the votes of the users listening to the track count
+        if !listeners.contains(&user_id) {
+            None
+        } else {
+            server.skip_votes.add(user_id);
 
-            return Ok(());
+            Some((server.skip_votes.count_among(&listeners), required_votes(listeners.len(), threshold)))
         }
     };
 
-    info!("goto - Next command invoked from guild {}!", guild_id.0);
-    let is_valid_index: bool;
-
-    {
-        let data = &mut ctx.data.write().await;
-
-        let server = get_server_mut(data, &guild_id)?;
-        let songs = &mut server.queue;
-
-        is_valid_index = index < songs.len();
+    let (votes, required) = match tally {
+        Some(tally) => tally,
+        None => {
+            check_msg(invocation.say(ctx, "Join the voice channel of the bot to vote to skip").await);
 
-        if is_valid_index {
-            for _ in 1..index {
-                songs.pop_front();
-            }
+            return Ok(false);
         }
-    }
+    };
 
-    if is_valid_index {
-        stop_current_track(ctx, &guild_id, Some(&msg.channel_id)).await?;
+    if votes >= required {
+        return Ok(true);
     }
 
-    Ok(())
+    check_msg(invocation.say(ctx, format!("Vote to skip registered ({votes}/{required})")).await);
+
+    Ok(false)
 }
 
-async fn stop_current_track(ctx: &Context, guild_id: &GuildId, channel_id: Option<&ChannelId>) -> CommandResult {
-    {
-        let data = ctx.data.read().await;
+fn get_bot_voice_channel(ctx: &Context, guild_id: &GuildId) -> Option<ChannelId> {
+    get_guild(ctx, guild_id).ok()?
+        .voice_states
+        .get(&ctx.cache.current_user_id())
+        .and_then(|voice_state| voice_state.channel_id)
+}
 
-        match get_track_handle(&data, guild_id).await {
-            Some(track_handle) => {
-                track_handle.stop()?
-            }
-            None => {
-                let error_message = "o_O Already stopped";
-                if let Some(channel) = channel_id {
-                    check_msg(channel.say(&ctx.http, error_message).await);
-                } else {
-                    return Err(CommandError::from(error_message));
-                }
-            }
-        }
-    }
+/// Users listening in the voice channel of the bot, excluding bots.
+fn get_voice_channel_listeners(ctx: &Context, guild_id: &GuildId) -> Vec<UserId> {
+    let bot_channel_id = match get_bot_voice_channel(ctx, guild_id) {
+        Some(bot_channel_id) => bot_channel_id,
+        None => return Vec::new(),
+    };
 
-    remove_track_handle(ctx, guild_id).await?;
+    let guild = match get_guild(ctx, guild_id) {
+        Ok(guild) => guild,
+        Err(_) => return Vec::new(),
+    };
 
-    Ok(())
+    guild.voice_states
+        .values()
+        .filter(|voice_state| voice_state.channel_id == Some(bot_channel_id))
+        .filter(|voice_state| {
+            let is_bot = voice_state.member
+                .as_ref()
+                .map(|member| member.user.bot)
+                .or_else(|| ctx.cache.user(voice_state.user_id).map(|user| user.bot))
+                .unwrap_or(false);
+
+            !is_bot
+        })
+        .map(|voice_state| voice_state.user_id)
+        .collect()
 }
 
-async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
-    leave_current_channel(ctx, msg).await
+#[command("voteskip")]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn vote_skip(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    vote_skip_command(ctx, &Invocation::from_message(msg), args.message()).await
 }
 
-async fn leave_current_channel(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild_id = get_guild_id(ctx, msg)?;
+/// Shows the vote skip setting if the input is empty, otherwise sets it. `off` or 0% lets anyone skip on their own.
+async fn vote_skip_command(ctx: &Context, invocation: &Invocation<'_>, user_input: &str) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+    let user_input = user_input.trim();
 
-    let manager = songbird::get(ctx).await
+    let new_threshold = match user_input {
+        "" => {
+            let data = ctx.data.read().await;
+            let threshold = get_server(&data, &guild_id).and_then(|server| server.skip_threshold);
+
+            check_msg(invocation.say(ctx, vote_skip_description(threshold)).await);
+
+            return Ok(());
+        }
+        _ => match parse_skip_threshold(user_input) {
+            Some(threshold) => threshold,
+            None => {
+                check_msg(invocation.say(ctx, "Invalid value. Use `on`, `off` or a percentage between 0 and 100.").await);
+
+                return Ok(());
+            }
+        },
+    };
+
+    {
+        let data = &mut ctx.data.write().await;
+        let server = get_or_insert_server_mut(data, &guild_id)?;
+
+        server.skip_threshold = new_threshold;
+        server.skip_votes.clear();
+    }
+
+    save_servers(&ctx.data).await;
+
+    check_msg(invocation.say(ctx, vote_skip_description(new_threshold)).await);
+
+    Ok(())
+}
+
+fn vote_skip_description(threshold: Option<u32>) -> String {
+    match threshold {
+        Some(threshold) => format!("Vote skip: **{threshold}%** of the listeners"),
+        None => "Vote skip: **off**, anyone can skip".to_string(),
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(DJ)]
+async fn shuffle(ctx: &Context, msg: &Message) -> CommandResult {
+    shuffle_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn shuffle_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    info!("Shuffle - Next command invoked from guild {}!", guild_id.0);
+
+    {
+        let data = &mut ctx.data.write().await;
+        let server = get_server_mut(data, &guild_id)?;
+
+        server.queue.shuffle();
+    }
+
+    save_servers(&ctx.data).await;
+
+    invocation.react(ctx, "👍").await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn seek(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    seek_command(ctx, &Invocation::from_message(msg), args.message()).await
+}
+
+async fn seek_command(ctx: &Context, invocation: &Invocation<'_>, user_input: &str) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    let seek_target = match SeekTarget::parse(user_input) {
+        Some(seek_target) => seek_target,
+        None => {
+            check_msg(invocation.say(ctx, "Invalid position. Use `mm:ss`, `+30` or `-30`.").await);
+
+            return Ok(());
+        }
+    };
+
+    let data = ctx.data.read().await;
+
+    let track_handle = match get_track_handle(&data, &guild_id).await {
+        Some(track_handle) => track_handle,
+        None => {
+            check_msg(invocation.say(ctx, "Nothing is playing").await);
+
+            return Ok(());
+        }
+    };
+
+    if !track_handle.is_seekable() {
+        check_msg(invocation.say(ctx, "This track doesn't support seeking").await);
+
+        return Ok(());
+    }
+
+    let current_position = track_handle.get_info().await?.position;
+    track_handle.seek_time(seek_target.position_from(current_position))?;
+
+    invocation.react(ctx, "👍").await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn volume(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    volume_command(ctx, &Invocation::from_message(msg), args.message()).await
+}
+
+/// Shows the volume if the input is empty, otherwise sets it.
+async fn volume_command(ctx: &Context, invocation: &Invocation<'_>, user_input: &str) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+    let user_input = user_input.trim();
+
+    if user_input.is_empty() {
+        let data = ctx.data.read().await;
+        let current_volume = get_server(&data, &guild_id).map(|server| server.volume).unwrap_or(DEFAULT_VOLUME);
+
+        check_msg(invocation.say(ctx, format!("Volume: **{current_volume}%**")).await);
+
+        return Ok(());
+    }
+
+    let new_volume = match user_input.parse::<u32>() {
+        Ok(new_volume) if new_volume <= MAX_VOLUME => new_volume,
+        _ => {
+            check_msg(invocation.say(ctx, format!("Invalid volume. Use a value between 0 and {MAX_VOLUME}.")).await);
+
+            return Ok(());
+        }
+    };
+
+    {
+        let data = &mut ctx.data.write().await;
+        let server = get_or_insert_server_mut(data, &guild_id)?;
+
+        server.volume = new_volume;
+
+        if let Some(track_handle) = &server.track_handle {
+            track_handle.set_volume(volume_ratio(new_volume))?;
+        }
+    }
+
+    save_servers(&ctx.data).await;
+
+    check_msg(invocation.say(ctx, format!("Volume: **{new_volume}%**")).await);
+
+    Ok(())
+}
+
+#[command("loop")]
+#[only_in(guilds)]
+async fn loop_mode(ctx: &Context, msg: &Message) -> CommandResult {
+    loop_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn loop_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+    let repeat_mode: RepeatMode;
+
+    {
+        let data = &mut ctx.data.write().await;
+        let server = get_or_insert_server_mut(data, &guild_id)?;
+
+        repeat_mode = server.repeat_mode.next();
+        server.repeat_mode = repeat_mode;
+    }
+
+    save_servers(&ctx.data).await;
+
+    check_msg(invocation.say(ctx, format!("Repeat mode: **{repeat_mode}**")).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(DJ)]
+async fn remove(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let input = args.message().trim();
+
+    let range = match input.split_once('-') {
+        Some((start, end)) => start.trim().parse::<usize>().ok().zip(end.trim().parse::<usize>().ok()),
+        None => input.parse::<usize>().ok().map(|index| (index, index)),
+    };
+
+    remove_command(ctx, &Invocation::from_message(msg), range).await
+}
+
+/// Removes the songs between the given 1-based indexes, both included.
+async fn remove_command(ctx: &Context, invocation: &Invocation<'_>, range: Option<(usize, usize)>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    let removed_songs = {
+        let data = &mut ctx.data.write().await;
+        let server = get_or_insert_server_mut(data, &guild_id)?;
+
+        range.and_then(|(start, end)| server.queue.remove_range(start, end))
+    };
+
+    match removed_songs.as_deref() {
+        Some([song]) => {
+            save_servers(&ctx.data).await;
+            check_msg(invocation.say(ctx, format!("Removed **{}**", song.title)).await);
+        }
+        Some(songs) => {
+            save_servers(&ctx.data).await;
+            check_msg(invocation.say(ctx, format!("Removed {} songs", songs.len())).await);
+        }
+        None => check_invalid_index(ctx, invocation, &guild_id).await,
+    }
+
+    Ok(())
+}
+
+#[command("move")]
+#[only_in(guilds)]
+#[checks(DJ)]
+async fn move_song(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let from = args.single::<usize>().ok();
+    let to = args.single::<usize>().ok();
+
+    move_command(ctx, &Invocation::from_message(msg), from, to).await
+}
+
+async fn move_command(ctx: &Context, invocation: &Invocation<'_>, from: Option<usize>, to: Option<usize>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    let moved_song = match (from, to) {
+        (Some(from), Some(to)) => {
+            let data = &mut ctx.data.write().await;
+            let server = get_or_insert_server_mut(data, &guild_id)?;
+
+            server.queue.move_song(from, to).map(|song| (song.title.clone(), to))
+        }
+        _ => None,
+    };
+
+    match moved_song {
+        Some((title, to)) => {
+            save_servers(&ctx.data).await;
+            check_msg(invocation.say(ctx, format!("Moved **{title}** to position {to}")).await);
+        }
+        None => check_invalid_index(ctx, invocation, &guild_id).await,
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(DJ)]
+async fn swap(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let first = args.single::<usize>().ok();
+    let second = args.single::<usize>().ok();
+
+    swap_command(ctx, &Invocation::from_message(msg), first, second).await
+}
+
+async fn swap_command(ctx: &Context, invocation: &Invocation<'_>, first: Option<usize>, second: Option<usize>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    let is_swapped = match (first, second) {
+        (Some(first), Some(second)) => {
+            let data = &mut ctx.data.write().await;
+            let server = get_or_insert_server_mut(data, &guild_id)?;
+
+            server.queue.swap(first, second)
+        }
+        _ => false,
+    };
+
+    if is_swapped {
+        save_servers(&ctx.data).await;
+        invocation.react(ctx, "👍").await?;
+    } else {
+        check_invalid_index(ctx, invocation, &guild_id).await;
+    }
+
+    Ok(())
+}
+
+/// Tells the user which indexes are valid after an index of the queue could not be used.
+async fn check_invalid_index(ctx: &Context, invocation: &Invocation<'_>, guild_id: &GuildId) {
+    let queue_len = get_queue_len(ctx, guild_id).await;
+
+    let message = if queue_len == 0 {
+        "The queue is empty!".to_string()
+    } else {
+        format!("Invalid song index. Use an index between 1 and {queue_len}, check the queue to list the songs.")
+    };
+
+    check_msg(invocation.say(ctx, message).await);
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(DJ)]
+async fn goto(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let index = args.single::<usize>().ok();
+
+    goto_command(ctx, &Invocation::from_message(msg), index).await
+}
+
+async fn goto_command(ctx: &Context, invocation: &Invocation<'_>, index: Option<usize>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    let index = match index {
+        Some(index) => index,
+        None => {
+            check_msg(invocation.say(ctx, "Invalid song index. Check the queue to list the songs.").await);
+
+            return Ok(());
+        }
+    };
+
+    info!("goto - Next command invoked from guild {}!", guild_id.0);
+    let is_valid_index: bool;
+
+    {
+        let data = &mut ctx.data.write().await;
+
+        let server = get_server_mut(data, &guild_id)?;
+
+        is_valid_index = server.queue.jump(index);
+    }
+
+    if is_valid_index {
+        save_servers(&ctx.data).await;
+        stop_current_track(ctx, &guild_id, Some(&invocation.channel_id())).await?;
+    } else {
+        check_msg(invocation.say(ctx, "Invalid song index. Check the queue to list the songs.").await);
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases("back")]
+#[checks(DJ)]
+async fn previous(ctx: &Context, msg: &Message) -> CommandResult {
+    previous_command(ctx, &Invocation::from_message(msg)).await
+}
+
+/// Plays again the last played song. The current song is put back at the top of the queue, so it's played right
+/// after and going back repeatedly walks through the history.
+async fn previous_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    let has_history = {
+        let data = ctx.data.read().await;
+        get_server(&data, &guild_id).map(|server| !server.history.is_empty()).unwrap_or(false)
+    };
+
+    // Nothing to go back to, there is no point in joining the voice channel
+    if !has_history {
+        check_msg(invocation.say(ctx, NO_HISTORY_MESSAGE).await);
+
+        return Ok(());
+    }
+
+    join(ctx, invocation).await?;
+    deafen(ctx, invocation).await?;
+
+    let is_playing: Option<bool>;
+
+    {
+        let data = &mut ctx.data.write().await;
+        let server = get_or_insert_server_mut(data, &guild_id)?;
+
+        is_playing = server.history.take_last().map(|previous_song| {
+            // Taking the current song out keeps it from being added to the history once it's stopped
+            let current_song = server.current_song.take().map(|mut song| {
+                song.start = None;
+                song
+            });
+
+            let mut songs = vec![previous_song];
+            songs.extend(current_song);
+            server.queue.insert_all(songs, QueuePosition::Front);
+
+            server.track_handle.is_some()
+        });
+    }
+
+    match is_playing {
+        Some(true) => {
+            // Stopping the current song will automatically start the previous one
+            stop_current_track(ctx, &guild_id, Some(&invocation.channel_id())).await?;
+        }
+        Some(false) => play_next_if_queue_empty(ctx, &guild_id, &invocation.channel_id()).await,
+        None => {
+            check_msg(invocation.say(ctx, NO_HISTORY_MESSAGE).await);
+
+            return Ok(());
+        }
+    }
+
+    save_servers(&ctx.data).await;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn history(ctx: &Context, msg: &Message) -> CommandResult {
+    history_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn history_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    let embed = {
+        let data = ctx.data.read().await;
+
+        get_server(&data, &guild_id)
+            .filter(|server| !server.history.is_empty())
+            .map(|server| history_embed(&server.history))
+    };
+
+    match embed {
+        Some(embed) => check_msg(invocation.send_embed(ctx, embed, None).await),
+        None => check_msg(invocation.say(ctx, NO_HISTORY_MESSAGE).await),
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn replay(ctx: &Context, msg: &Message) -> CommandResult {
+    replay_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn replay_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    let data = ctx.data.read().await;
+
+    let track_handle = match get_track_handle(&data, &guild_id).await {
+        Some(track_handle) => track_handle,
+        None => {
+            check_msg(invocation.say(ctx, "Nothing is playing").await);
+
+            return Ok(());
+        }
+    };
+
+    if !track_handle.is_seekable() {
+        check_msg(invocation.say(ctx, "This track can't be restarted").await);
+
+        return Ok(());
+    }
+
+    track_handle.seek_time(Duration::ZERO)?;
+
+    invocation.react(ctx, "👍").await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn playlist(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    playlist_command(ctx, &Invocation::from_message(msg), &PlaylistRequest::parse(args.message())?).await
+}
+
+/// Manages the saved playlists of the server, or the ones of the user if the request is personal. Changing the
+/// playlists of the server is restricted to DJs, like the commands that change the queue of everyone.
+async fn playlist_command(ctx: &Context, invocation: &Invocation<'_>, request: &PlaylistRequest) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+    let name = request.name.as_str();
+
+    let owner = if request.personal {
+        PlaylistOwner::User(invocation.author_id())
+    } else {
+        PlaylistOwner::Guild(guild_id)
+    };
+
+    let changes_server_playlist = !request.personal
+        && matches!(request.action, PlaylistAction::Save | PlaylistAction::Delete | PlaylistAction::Add { .. });
+
+    if changes_server_playlist && !is_dj(ctx, &guild_id, invocation.author_id()).await {
+        check_msg(invocation.say(ctx, NOT_A_DJ_MESSAGE).await);
+
+        return Ok(());
+    }
+
+    match &request.action {
+        PlaylistAction::Save => save_playlist(ctx, invocation, &guild_id, owner, name).await,
+        PlaylistAction::Load => load_playlist(ctx, invocation, &guild_id, owner, name).await,
+        PlaylistAction::List => list_playlists(ctx, invocation, &guild_id).await,
+        PlaylistAction::Delete => delete_playlist(ctx, invocation, owner, name).await,
+        PlaylistAction::Add { query } => add_to_playlist(ctx, invocation, &guild_id, owner, name, query).await,
+    }
+}
+
+async fn save_playlist(ctx: &Context, invocation: &Invocation<'_>, guild_id: &GuildId, owner: PlaylistOwner, name: &str) -> CommandResult {
+    let songs: Vec<Song> = {
+        let data = ctx.data.read().await;
+
+        match get_server(&data, guild_id) {
+            Some(server) => server.current_song
+                .iter()
+                .chain(server.queue.iter())
+                .cloned()
+                .map(|mut song| {
+                    // Saved songs are played from the beginning
+                    song.start = None;
+                    song
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    };
+
+    if songs.is_empty() {
+        check_msg(invocation.say(ctx, "The queue is empty, there's nothing to save").await);
+
+        return Ok(());
+    }
+
+    let songs_count = songs.len();
+
+    {
+        let data = &mut ctx.data.write().await;
+        get_saved_playlists_mut(data)?.save(owner, name, songs)?;
+    }
+
+    save_playlists(&ctx.data).await;
+
+    check_msg(invocation.say(ctx, format!("Saved **{name}** with {songs_count} songs")).await);
+
+    Ok(())
+}
+
+async fn load_playlist(ctx: &Context, invocation: &Invocation<'_>, guild_id: &GuildId, owner: PlaylistOwner, name: &str) -> CommandResult {
+    let (songs, saved_songs_count, max_songs) = {
+        let data = ctx.data.read().await;
+
+        let saved_songs = data.get::<SavedPlaylistsMap>()
+            .and_then(|saved_playlists| saved_playlists.get(owner, name))
+            .map(|saved_playlist| &saved_playlist.songs)
+            .ok_or(DubaError::SavedPlaylistNotFound { name: name.to_string() })?;
+
+        // Loading a playlist is a single request, it's limited like the playlists of the play commands
+        let max_songs = get_server(&data, guild_id)
+            .map(|server| server.max_playlist_songs)
+            .unwrap_or(DEFAULT_MAX_PLAYLIST_SONGS);
+        let songs: Vec<Song> = saved_songs.iter().take(max_songs).cloned().collect();
+
+        (songs, saved_songs.len(), max_songs)
+    };
+
+    join(ctx, invocation).await?;
+    deafen(ctx, invocation).await?;
+
+    let requester = invocation.requester();
+    let songs_count = songs.len();
+    let songs = songs.into_iter().map(|song| song.requested_by(requester.clone())).collect();
+
+    push_songs_to_guild(ctx, guild_id, songs, QueuePosition::Back).await?;
+    play_next_if_queue_empty(ctx, guild_id, &invocation.channel_id()).await;
+    save_servers(&ctx.data).await;
+
+    let mut message = format!("Queued {songs_count} songs from **{name}**");
+
+    if saved_songs_count > songs_count {
+        message.push_str(&format!(". The rest was left out, as this server allows {max_songs} songs per request"));
+    }
+
+    check_msg(invocation.say(ctx, message).await);
+
+    Ok(())
+}
+
+async fn list_playlists(ctx: &Context, invocation: &Invocation<'_>, guild_id: &GuildId) -> CommandResult {
+    let embed = {
+        let data = ctx.data.read().await;
+
+        data.get::<SavedPlaylistsMap>().map(|saved_playlists| {
+            saved_playlists_embed(
+                &saved_playlists.list(PlaylistOwner::Guild(*guild_id)),
+                &saved_playlists.list(PlaylistOwner::User(invocation.author_id())),
+            )
+        })
+    };
+
+    if let Some(embed) = embed {
+        check_msg(invocation.send_embed(ctx, embed, None).await);
+    }
+
+    Ok(())
+}
+
+async fn delete_playlist(ctx: &Context, invocation: &Invocation<'_>, owner: PlaylistOwner, name: &str) -> CommandResult {
+    let is_deleted = {
+        let data = &mut ctx.data.write().await;
+        get_saved_playlists_mut(data)?.delete(owner, name)
+    };
+
+    if !is_deleted {
+        return Err(DubaError::SavedPlaylistNotFound { name: name.to_string() }.into());
+    }
+
+    save_playlists(&ctx.data).await;
+
+    check_msg(invocation.say(ctx, format!("Deleted **{name}**")).await);
+
+    Ok(())
+}
+
+async fn add_to_playlist(
+    ctx: &Context,
+    invocation: &Invocation<'_>,
+    guild_id: &GuildId,
+    owner: PlaylistOwner,
+    name: &str,
+    query: &str,
+) -> CommandResult {
+    invocation.show_loading(ctx).await?;
+
+    let songs = resolve_songs_to_save(ctx, guild_id, query).await;
+
+    invocation.hide_loading(ctx).await?;
+
+    let songs = songs?;
+
+    let message = match songs.as_slice() {
+        [] => return Err(DubaError::PlaylistEmpty { url: query.to_string() }.into()),
+        [song] => format!("Added **{}** to **{name}**", song.title),
+        songs => format!("Added {} songs to **{name}**", songs.len()),
+    };
+
+    let songs_count = {
+        let data = &mut ctx.data.write().await;
+        get_saved_playlists_mut(data)?.add_songs(owner, name, songs)?
+    };
+
+    save_playlists(&ctx.data).await;
+
+    check_msg(invocation.say(ctx, format!("{message}, which has {songs_count} songs now")).await);
+
+    Ok(())
+}
+
+/// Finds the songs of a URL or the first result of a search. All the songs of a playlist URL are returned, up to the
+/// number of songs a single request can queue in the server.
+async fn resolve_songs_to_save(ctx: &Context, guild_id: &GuildId, query: &str) -> Result<Vec<Song>, CommandError> {
+    let resolver = get_resolver(ctx).await?;
+
+    let max_songs = {
+        let data = ctx.data.read().await;
+        get_server(&data, guild_id).map(|server| server.max_playlist_songs).unwrap_or(DEFAULT_MAX_PLAYLIST_SONGS)
+    };
+
+    resolve_songs(resolver.as_ref(), query, max_songs).await
+}
+
+fn get_saved_playlists_mut<'a>(data: &'a mut RwLockWriteGuard<TypeMap>) -> Result<&'a mut SavedPlaylists, CommandError> {
+    data.get_mut::<SavedPlaylistsMap>().ok_or(CommandError::from("Saved playlists are not loaded"))
+}
+
+#[command("djrole")]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn dj_role(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    dj_role_command(ctx, &Invocation::from_message(msg), args.message()).await
+}
+
+/// Shows the DJ role if the input is empty, otherwise sets it from a role mention or ID. `off` removes it.
+async fn dj_role_command(ctx: &Context, invocation: &Invocation<'_>, user_input: &str) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+    let user_input = user_input.trim();
+
+    let new_dj_role = match user_input {
+        "" => {
+            let data = ctx.data.read().await;
+            let dj_role = get_server(&data, &guild_id).and_then(|server| server.dj_role);
+
+            check_msg(invocation.say(ctx, dj_role_description(dj_role)).await);
+
+            return Ok(());
+        }
+        "off" => None,
+        _ => match RoleId::from_str(user_input) {
+            Ok(role_id) => Some(role_id),
+            Err(_) => {
+                check_msg(invocation.say(ctx, "Invalid role. Mention the role or use `off`.").await);
+
+                return Ok(());
+            }
+        },
+    };
+
+    {
+        let data = &mut ctx.data.write().await;
+        let server = get_or_insert_server_mut(data, &guild_id)?;
+
+        server.dj_role = new_dj_role;
+    }
+
+    save_servers(&ctx.data).await;
+
+    check_msg(invocation.say(ctx, dj_role_description(new_dj_role)).await);
+
+    Ok(())
+}
+
+fn dj_role_description(dj_role: Option<RoleId>) -> String {
+    match dj_role {
+        Some(dj_role) => format!("DJ role: <@&{}>", dj_role.0),
+        None => "DJ role: **none**, anyone can manage the queue".to_string(),
+    }
+}
+
+#[command("maxsongs")]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn max_songs(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    max_songs_command(ctx, &Invocation::from_message(msg), args.message()).await
+}
+
+/// Shows how many songs a single request can queue if the input is empty, otherwise sets it.
+async fn max_songs_command(ctx: &Context, invocation: &Invocation<'_>, user_input: &str) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+    let user_input = user_input.trim();
+
+    if user_input.is_empty() {
+        let data = ctx.data.read().await;
+        let max_songs = get_server(&data, &guild_id)
+            .map(|server| server.max_playlist_songs)
+            .unwrap_or(DEFAULT_MAX_PLAYLIST_SONGS);
+
+        check_msg(invocation.say(ctx, format!("Songs per request: **{max_songs}**")).await);
+
+        return Ok(());
+    }
+
+    let new_max_songs = match user_input.parse::<usize>() {
+        Ok(new_max_songs) if new_max_songs >= 1 => new_max_songs,
+        _ => {
+            check_msg(invocation.say(ctx, "Invalid number of songs. Use a number of 1 or more.").await);
+
+            return Ok(());
+        }
+    };
+
+    {
+        let data = &mut ctx.data.write().await;
+        let server = get_or_insert_server_mut(data, &guild_id)?;
+
+        server.max_playlist_songs = new_max_songs;
+    }
+
+    save_servers(&ctx.data).await;
+
+    check_msg(invocation.say(ctx, format!("Songs per request: **{new_max_songs}**")).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+async fn resume(ctx: &Context, msg: &Message) -> CommandResult {
+    resume_command(ctx, &Invocation::from_message(msg)).await
+}
+
+async fn resume_command(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    let has_snapshot = {
+        let data = ctx.data.read().await;
+        data.get::<ServersManager>()
+            .map(|duba_servers| duba_servers.resumable.contains_key(&guild_id.0))
+            .unwrap_or(false)
+    };
+
+    if !has_snapshot {
+        check_msg(invocation.say(ctx, "There is nothing to resume").await);
+
+        return Ok(());
+    }
+
+    join(ctx, invocation).await?;
+    deafen(ctx, invocation).await?;
+
+    {
+        let data = &mut ctx.data.write().await;
+        let duba_servers = data.get_mut::<ServersManager>().ok_or(DubaError::GuildNotFound)?;
+
+        if let Some(snapshot) = duba_servers.resumable.remove(&guild_id.0) {
+            let server = duba_servers.servers.entry(guild_id.0).or_default();
+            server.queue.enqueue_all(snapshot.into_songs());
+        }
+    }
+
+    play_next_if_queue_empty(ctx, &guild_id, &invocation.channel_id()).await;
+    save_servers(&ctx.data).await;
+
+    invocation.react(ctx, "👍").await?;
+
+    Ok(())
+}
+
+async fn stop_current_track(ctx: &Context, guild_id: &GuildId, channel_id: Option<&ChannelId>) -> CommandResult {
+    // The track is forgotten before being stopped, so its end event can't mistake it for a song that ended on its
+    // own and repeat it
+    let track_handle = {
+        let data = &mut ctx.data.write().await;
+
+        get_server_mut(data, guild_id).ok().and_then(|server| take_current_track(server, true).0)
+    };
+
+    match track_handle {
+        Some(track_handle) => {
+            save_servers(&ctx.data).await;
+            track_handle.stop()?
+        }
+        None => {
+            if let Some(channel) = channel_id {
+                check_msg(channel.say(&ctx.http, DubaError::AlreadyStopped.user_message()).await);
+            } else {
+                return Err(DubaError::AlreadyStopped.into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn leave(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    leave_current_channel(ctx, invocation).await
+}
+
+async fn leave_current_channel(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
+
+    let manager = songbird::get(ctx).await
         .expect("Songbird Voice client placed in at initialisation.").clone();
 
     let has_handler = manager.get(guild_id).is_some();
 
     if has_handler {
         if let Err(e) = manager.remove(guild_id).await {
-            check_msg(msg.channel_id.say(&ctx.http, format!("Failed: {e:?}")).await);
+            check_msg(invocation.say(ctx, format!("Failed: {e:?}")).await);
         }
 
-        check_msg(msg.channel_id.say(&ctx.http, "Left voice channel").await);
+        check_msg(invocation.say(ctx, "Left voice channel").await);
     } else {
-        check_msg(msg.reply(ctx, "Not in a voice channel").await);
+        check_msg(invocation.reply(ctx, "Not in a voice channel").await);
     }
 
     Ok(())
 }
 
-async fn play_next_song(ctx: &Context, guild_id: &GuildId, channel_id: &ChannelId) -> Result<(), CommandError> {
-    if let Some(song) = get_next_song(ctx, guild_id).await {
-        info!("PLAY_NEXT_SONG - Next song is {} - {}", song.title, song.url);
-
-        let manager = songbird::get(ctx).await
-            .expect("Songbird Voice client placed in at initialisation.").clone();
+/// Plays the next song of the queue that can be started in a separate task, so the retries don't hold up the
+/// commands and the track events. Nothing is done if a song is already playing or being started, as starting another
+/// one would stop it.
+async fn play_next_available_song(ctx: &Context, guild_id: &GuildId, channel_id: &ChannelId) {
+    {
+        let data = &mut ctx.data.write().await;
 
-        if let Some(handler_lock) = manager.get(*guild_id) {
-            let mut handler = handler_lock.lock().await;
+        match get_server_mut(data, guild_id) {
+            Ok(server) if server.claim_start() => {}
+            _ => return,
+        }
+    }
 
-            let source = match ytdl(&song.url).await {
-                Ok(source) => source,
-                Err(why) => {
-                    check_msg(channel_id.say(&ctx.http, format!("Could not play {} due to error {}", song.title, why)).await);
+    let ctx = ctx.clone();
+    let guild_id = *guild_id;
+    let channel_id = *channel_id;
 
-                    info!("Err starting source: {why:?}");
+    tokio::spawn(async move {
+        play_first_available_song(&ctx, &guild_id, &channel_id).await;
+    });
+}
 
-                    return Err(CommandError::from(why));
+/// Plays the next song of the queue that can be started. Each song is tried a few times before being skipped, and
+/// the skipped songs are reported in a single message. As many failures in a row usually mean that yt-dlp itself is
+/// broken, the retries slow down after a few of them, and the queue is cleared if they keep failing.
+async fn play_first_available_song(ctx: &Context, guild_id: &GuildId, channel_id: &ChannelId) {
+    let mut skipped_songs: Vec<String> = Vec::new();
+    let mut backoff = RETRY_INITIAL_BACKOFF;
+    let mut is_playing = false;
+
+    while let Some(song) = get_next_song(ctx, guild_id).await {
+        let mut is_in_voice_channel = true;
+
+        for attempt in 1..=MAX_ATTEMPTS_PER_SONG {
+            match play_song_now(ctx, guild_id, channel_id, &song).await {
+                Ok(()) => {
+                    is_playing = true;
+                    break;
                 }
-            };
+                Err(why) if matches!(why.downcast_ref::<DubaError>(), Some(DubaError::NotInVoiceChannel)) => {
+                    is_in_voice_channel = false;
+                    break;
+                }
+                Err(why) => info!("Attempt {attempt} to play {} failed: {why:?}", song.title),
+            }
+        }
+
+        if is_playing {
+            break;
+        }
+
+        // The song isn't at fault, so it stays first in the queue
+        if !is_in_voice_channel {
+            let title = song.title.clone();
 
-            handler.stop(); // Just in case something was playing before
-            let track_handle = handler.play_source(source);
-
-            track_handle.add_event(
-                Event::Track(End),
-                SongEndNotifier {
-                    guild_id: *guild_id,
-                    channel_id: *channel_id,
-                    ctx: ctx.clone(),
-                },
-            ).expect("Add event END failed");
-
-            set_new_track_handle(track_handle, ctx, guild_id).await?;
-
-            // TODO
-            // let duration_text = if let Some(duration) = &song.duration {
-            //     let seconds = duration.as_secs();
-            //     let minutes = seconds / 60;
-            //     let display_seconds = seconds - (minutes * 60);
-            //
-            //     format!("\n> Duration: `{}:{:0>2}`", minutes, display_seconds)
-            // } else {
-            //     "".to_string()
-            // };
+            if let Err(why) = push_song_to_guild(ctx, guild_id, song, QueuePosition::Front).await {
+                info!("Putting back {title} failed: {why:?}");
+            }
+
+            check_msg(channel_id.say(&ctx.http, "Not in a voice channel to play in").await);
+
+            break;
+        }
+
+        skipped_songs.push(song.title);
+
+        if skipped_songs.len() >= MAX_CONSECUTIVE_FAILURES {
+            if let Err(why) = clear_queue(ctx, guild_id).await {
+                info!("Clearing the queue failed: {why:?}");
+            }
 
             check_msg(
                 channel_id.say(
                     &ctx.http,
-                    format!("Playing song [{}]({})", song.title, song.url),
+                    format!("Stopped playing and cleared the queue after {} songs failed in a row", skipped_songs.len()),
                 ).await
             );
-        } else {
-            check_msg(channel_id.say(&ctx.http, "Not in a voice channel to play in").await);
+
+            break;
+        }
+
+        if skipped_songs.len() >= BACKOFF_AFTER_FAILURES {
+            info!("{} songs failed in a row, waiting {backoff:?} before the next one", skipped_songs.len());
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RETRY_MAX_BACKOFF);
+        }
+    }
+
+    if !is_playing {
+        // Otherwise the start was over as soon as the track was set
+        end_start(ctx, guild_id).await;
+    }
+
+    if !skipped_songs.is_empty() {
+        check_msg(
+            channel_id.say(
+                &ctx.http,
+                format!("Skipped {} songs that could not be played: {}", skipped_songs.len(), skipped_songs.join(", ")),
+            ).await
+        );
+    }
+
+    if !is_playing {
+        start_idle_timer(ctx, guild_id).await;
+    }
+}
+
+async fn play_song_now(ctx: &Context, guild_id: &GuildId, channel_id: &ChannelId, song: &Song) -> Result<(), CommandError> {
+    info!("PLAY_SONG_NOW - Next song is {} - {}", song.title, song.url);
+
+    let manager = songbird::get(ctx).await
+        .expect("Songbird Voice client placed in at initialisation.").clone();
+
+    let handler_lock = match manager.get(*guild_id) {
+        Some(handler_lock) => handler_lock,
+        None => return Err(DubaError::NotInVoiceChannel.into()),
+    };
+
+    let mut handler = handler_lock.lock().await;
+
+    // Restartable sources can seek backwards, unlike the plain ytdl ones
+    let source = Restartable::ytdl(song.url.clone(), false).await?;
+
+    handler.stop(); // Just in case something was playing before
+    let track_handle = handler.play_source(source.into());
+
+    let volume: u32;
+
+    {
+        let data = ctx.data.read().await;
+        volume = get_server(&data, guild_id).map(|server| server.volume).unwrap_or(DEFAULT_VOLUME);
+    }
+
+    if let Err(why) = track_handle.set_volume(volume_ratio(volume)) {
+        info!("Setting the volume of {} failed: {why:?}", song.title);
+    }
+
+    if let Some(start) = song.start {
+        if let Err(why) = track_handle.seek_time(start) {
+            info!("Seeking to the start of {} failed: {why:?}", song.title);
         }
     }
 
+    track_handle.add_event(
+        Event::Track(End),
+        SongEndNotifier {
+            guild_id: *guild_id,
+            channel_id: *channel_id,
+            ctx: ctx.clone(),
+        },
+    ).expect("Add event END failed");
+
+    set_new_track_handle(track_handle, song, channel_id, ctx, guild_id).await?;
+
+    let next_song: Option<Song>;
+
+    {
+        let data = ctx.data.read().await;
+        next_song = get_server(&data, guild_id).and_then(|server| server.queue.iter().next().cloned());
+    }
+
+    check_msg(
+        channel_id.send_message(&ctx.http, |m| {
+            m.embed(|e| now_playing_embed(e, song, song.start, next_song.as_ref()))
+        }).await
+    );
+
     Ok(())
 }
 
-async fn join(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild_id = get_guild_id(ctx, msg)?;
+async fn join(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
 
-    let channel_id = get_guild(ctx, msg)?
-        .voice_states.get(&msg.author.id)
+    let channel_id = get_guild(ctx, &guild_id)?
+        .voice_states.get(&invocation.author_id())
         .and_then(|voice_state| voice_state.channel_id);
 
     let connect_to = match channel_id {
         Some(channel) => channel,
-        None => {
-            check_msg(msg.reply(ctx, "Not in a voice channel").await);
-
-            return Err(CommandError::from("Not in a voice channel"));
-        }
+        None => return Err(DubaError::NotInVoiceChannel.into()),
     };
 
     let manager = songbird::get(ctx).await
@@ -586,8 +2060,8 @@ async fn join(ctx: &Context, msg: &Message) -> CommandResult {
     Ok(())
 }
 
-async fn deafen(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild_id = get_guild_id(ctx, msg)?;
+async fn deafen(ctx: &Context, invocation: &Invocation<'_>) -> CommandResult {
+    let guild_id = invocation.guild_id()?;
 
     let manager = songbird::get(ctx).await
         .expect("Songbird Voice client placed in at initialisation.").clone();
@@ -595,7 +2069,7 @@ async fn deafen(ctx: &Context, msg: &Message) -> CommandResult {
     let handler_lock = match manager.get(guild_id) {
         Some(handler) => handler,
         None => {
-            check_msg(msg.reply(ctx, "Not in a voice channel").await);
+            check_msg(invocation.reply(ctx, "Not in a voice channel").await);
 
             return Ok(());
         }
@@ -613,19 +2087,88 @@ async fn deafen(ctx: &Context, msg: &Message) -> CommandResult {
 }
 
 
-async fn set_new_track_handle(track_handle: TrackHandle, ctx: &Context, guild_id: &GuildId) -> Result<(), CommandError> {
-    let data = &mut ctx.data.write().await;
-    let server = get_server_mut(data, guild_id)?;
+async fn set_new_track_handle(track_handle: TrackHandle, song: &Song, channel_id: &ChannelId, ctx: &Context, guild_id: &GuildId) -> Result<(), CommandError> {
+    {
+        let data = &mut ctx.data.write().await;
+        let server = get_server_mut(data, guild_id)?;
+
+        server.track_handle = Some(track_handle);
+        server.is_starting = false;
+        server.current_song = Some(song.clone());
+        server.skip_votes.clear();
+        server.channel_id = Some(*channel_id);
+    }
 
-    server.track_handle = Some(track_handle);
+    save_servers(&ctx.data).await;
 
     Ok(())
 }
 
-async fn remove_track_handle(ctx: &Context, guild_id: &GuildId) -> Result<(), CommandError> {
+/// Lets another song be started after none of the queue could be.
+async fn end_start(ctx: &Context, guild_id: &GuildId) {
+    let data = &mut ctx.data.write().await;
+
+    if let Ok(server) = get_server_mut(data, guild_id) {
+        server.is_starting = false;
+    }
+}
+
+async fn reset_idle_state(ctx: &Context, guild_id: &GuildId) {
+    let data = &mut ctx.data.write().await;
+
+    if let Ok(server) = get_server_mut(data, guild_id) {
+        server.idle.reset();
+    }
+}
+
+/// Forgets the track that has just ended if it's still the current one, returning its song. Stopped tracks have
+/// already been forgotten, and another track may be playing since.
+async fn remove_track_handle(ctx: &Context, guild_id: &GuildId, ended_track: Option<&TrackHandle>) -> Result<Option<Song>, CommandError> {
+    let finished_song: Option<Song>;
+
+    {
+        let data = &mut ctx.data.write().await;
+        let server = get_server_mut(data, guild_id)?;
+
+        let is_current = server.track_handle
+            .as_ref()
+            .zip(ended_track)
+            .is_some_and(|(current_track, ended_track)| current_track.uuid() == ended_track.uuid());
+
+        if !is_current {
+            return Ok(None);
+        }
+
+        // A song repeated by the track mode is played again right away, it would only fill the history
+        let record_in_history = server.repeat_mode != RepeatMode::Track;
+        finished_song = take_current_track(server, record_in_history).1;
+    }
+
+    save_servers(&ctx.data).await;
+
+    Ok(finished_song)
+}
+
+/// Forgets the current track of the server, returning it along with its song, which can be added to the history.
+fn take_current_track(server: &mut ServerData, record_in_history: bool) -> (Option<TrackHandle>, Option<Song>) {
+    let track_handle = server.track_handle.take();
+    let song = server.current_song.take();
+    server.skip_votes.clear();
+
+    if let Some(song) = song.as_ref().filter(|_| record_in_history) {
+        server.history.record(song.clone());
+    }
+
+    (track_handle, song)
+}
+
+/// Puts back the song that has just finished if the guild has a repeat mode enabled.
+async fn requeue_finished_song(ctx: &Context, guild_id: &GuildId, song: Song) -> Result<(), CommandError> {
     let data = &mut ctx.data.write().await;
     let server = get_server_mut(data, guild_id)?;
-    server.track_handle = None;
+    let repeat_mode = server.repeat_mode;
+
+    server.queue.requeue(song, repeat_mode);
 
     Ok(())
 }
@@ -639,22 +2182,21 @@ async fn get_track_handle<'a>(data: &'a RwLockReadGuard<'a, TypeMap>, guild_id:
     return guild.track_handle.as_ref();
 }
 
-async fn get_songs_from_guild<'a>(data: &'a RwLockReadGuard<'_, TypeMap>, guild_id: &GuildId) -> &'a VecDeque<Song> {
-    let duba_guild = data.get::<ServersManager>().expect("Guild get failed");
-    let guilds = &duba_guild.servers;
-    let guild = guilds.get(&guild_id.0).expect("Guild should exist");
+async fn get_songs_from_guild<'a>(data: &'a RwLockReadGuard<'_, TypeMap>, guild_id: &GuildId) -> Result<&'a GuildQueue, CommandError> {
+    // Servers only get their data once something has been queued in them
+    let guild = get_server(data, guild_id).ok_or(DubaError::GuildNotFound)?;
 
     let count = guild.queue.len();
 
     info!("There are {count} songs:");
 
-    &guild.queue
+    Ok(&guild.queue)
 }
 
 async fn get_next_song(ctx: &Context, guild_id: &GuildId) -> Option<Song> {
     let data = &mut ctx.data.write().await;
     let server = get_server_mut(data, guild_id).ok()?;
-    let song = server.queue.pop_front();
+    let song = server.queue.advance();
 
     match &song {
         None => info!("GET_NEXT_SONG - Queue is empty"),
@@ -664,56 +2206,28 @@ async fn get_next_song(ctx: &Context, guild_id: &GuildId) -> Option<Song> {
     song
 }
 
-async fn push_song_to_guild(ctx: &Context, guild_id: &GuildId, song: Song, insert_last: bool) -> Result<(), CommandError> {
-    let data = &mut ctx.data.write().await;
-    let duba_guild = data.get_mut::<ServersManager>().ok_or(CommandError::from("Guild not found"))?;
-    let guilds = &mut duba_guild.servers;
-    let guild = guilds.get_mut(&guild_id.0);
-
-    match guild {
-        Some(data) => {
-            if insert_last {
-                data.queue.push_back(song);
-            } else {
-                data.queue.push_front(song);
-            }
-        }
-        None => {
-            let new_guild_data = ServerData {
-                track_handle: None,
-                queue: VecDeque::from([song]),
-            };
-
-            guilds.insert(guild_id.0, new_guild_data);
-        }
-    };
-
-    Ok(())
+async fn push_song_to_guild(ctx: &Context, guild_id: &GuildId, song: Song, position: QueuePosition) -> Result<(), CommandError> {
+    push_songs_to_guild(ctx, guild_id, vec![song], position).await
 }
 
-async fn push_songs_list_to_server(ctx: &Context, guild_id: &GuildId, songs: Vec<Song>) -> Result<(), CommandError> {
+/// Adds the songs to the queue at the given position, keeping their order.
+async fn push_songs_to_guild(ctx: &Context, guild_id: &GuildId, songs: Vec<Song>, position: QueuePosition) -> Result<(), CommandError> {
     let data = &mut ctx.data.write().await;
-    let duba_guild = data.get_mut::<ServersManager>().ok_or(CommandError::from("Guild not found"))?;
-    let servers = &mut duba_guild.servers;
-    let server = servers.get_mut(&guild_id.0);
+    let server = get_or_insert_server_mut(data, guild_id)?;
 
-    match server {
-        Some(data) => {
-            let queue = &mut data.queue;
-            let mut new_songs = VecDeque::from(songs);
-            queue.append(&mut new_songs);
-        }
-        None => {
-            let new_guild_data = ServerData {
-                track_handle: None,
-                queue: VecDeque::from(songs),
-            };
+    if server.queue.insert_all(songs, position) {
+        Ok(())
+    } else {
+        Err(DubaError::QueueIndexOutOfRange.into())
+    }
+}
 
-            servers.insert(guild_id.0, new_guild_data);
-        }
-    };
+async fn get_queue_len(ctx: &Context, guild_id: &GuildId) -> usize {
+    let data = ctx.data.read().await;
 
-    Ok(())
+    get_server(&data, guild_id)
+        .map(|server| server.queue.len())
+        .unwrap_or(0)
 }
 
 /// Checks that a message successfully sent; if not, then logs why to stdout.
@@ -723,14 +2237,23 @@ fn check_msg(result: SerenityResult<Message>) {
     }
 }
 
-fn get_guild(ctx: &Context, msg: &Message) -> CommandResult<Guild> {
-    msg.guild(&ctx.cache).ok_or(CommandError::from("Guild not found"))
+/// Replaces the content of a message previously sent by the bot, logging why if it fails.
+async fn edit_message(ctx: &Context, message: &mut Message, content: String) {
+    if let Err(why) = message.edit(&ctx.http, |m| m.content(content)).await {
+        info!("Error editing message: {why:?}");
+    }
 }
 
-fn get_guild_id(ctx: &Context, msg: &Message) -> CommandResult<GuildId> {
-    let guild_id = get_guild(ctx, msg)?.id;
+async fn get_resolver(ctx: &Context) -> Result<Arc<dyn TrackResolver>, CommandError> {
+    let data = ctx.data.read().await;
+
+    data.get::<ResolverMap>()
+        .cloned()
+        .ok_or(DubaError::ResolverNotFound.into())
+}
 
-    Ok(guild_id)
+fn get_guild(ctx: &Context, guild_id: &GuildId) -> CommandResult<Guild> {
+    guild_id.to_guild_cached(&ctx.cache).ok_or(DubaError::GuildNotFound.into())
 }
 
 struct SongEndNotifier {
@@ -741,13 +2264,26 @@ struct SongEndNotifier {
 
 #[async_trait]
 impl VoiceEventHandler for SongEndNotifier {
-    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
         info!("End notifier triggered");
 
-        match remove_track_handle(&self.ctx, &self.guild_id).await {
-            Ok(_) => {
-                // If playing next song fails, try with the another one until it works
-                while (play_next_song(&self.ctx, &self.guild_id, &self.channel_id).await).is_err() {}
+        let ended_track = match ctx {
+            EventContext::Track(tracks) => tracks.first().map(|(_, track_handle)| *track_handle),
+            _ => None,
+        };
+
+        match remove_track_handle(&self.ctx, &self.guild_id, ended_track).await {
+            Ok(finished_song) => {
+                // Skipped or stopped songs have already been removed, so only songs that ended on their own are
+                // repeated
+                if let Some(song) = finished_song {
+                    if let Err(error) = requeue_finished_song(&self.ctx, &self.guild_id, song).await {
+                        info!("{:#?}", error)
+                    }
+                }
+
+                // Nothing is started if a new track has been started, or is being started, since this one was stopped
+                play_next_available_song(&self.ctx, &self.guild_id, &self.channel_id).await;
             }
             Err(_) => { info!("Remove track failed") }
         }
@@ -756,10 +2292,26 @@ impl VoiceEventHandler for SongEndNotifier {
     }
 }
 
+/// Converts a volume percentage into songbird's volume, where 1.0 is the original volume.
+fn volume_ratio(volume: u32) -> f32 {
+    volume as f32 / 100.0
+}
+
+fn get_server<'a>(data: &'a RwLockReadGuard<TypeMap>, guild_id: &GuildId) -> Option<&'a ServerData> {
+    data.get::<ServersManager>()?.servers.get(&guild_id.0)
+}
+
 fn get_server_mut<'a>(data: &'a mut RwLockWriteGuard<TypeMap>, guild_id: &GuildId) -> Result<&'a mut ServerData, CommandError> {
-    let duba_guild = data.get_mut::<ServersManager>().ok_or(CommandError::from("Guild not found"))?;
+    let duba_guild = data.get_mut::<ServersManager>().ok_or(DubaError::GuildNotFound)?;
     let servers = &mut duba_guild.servers;
-    let server = servers.get_mut(&guild_id.0).ok_or(CommandError::from("Guild not found"))?;
+    let server = servers.get_mut(&guild_id.0).ok_or(DubaError::GuildNotFound)?;
+
+    Ok(server)
+}
+
+fn get_or_insert_server_mut<'a>(data: &'a mut RwLockWriteGuard<TypeMap>, guild_id: &GuildId) -> Result<&'a mut ServerData, CommandError> {
+    let duba_guild = data.get_mut::<ServersManager>().ok_or(DubaError::GuildNotFound)?;
+    let server = duba_guild.servers.entry(guild_id.0).or_default();
 
     Ok(server)
 }
\ No newline at end of file
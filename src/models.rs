@@ -1,19 +1,156 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{ChannelId, RoleId, UserId};
+use serenity::model::Timestamp;
 use songbird::tracks::TrackHandle;
 
-#[derive(Clone)]
+use crate::history::PlayHistory;
+use crate::idle::IdleState;
+use crate::persistence::ServerSnapshot;
+use crate::queue::{GuildQueue, RepeatMode};
+use crate::votes::SkipVotes;
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Song {
     pub title: String,
     pub url: String,
     pub duration: Option<Duration>,
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+    /// Position where the song starts playing, e.g. from a timestamped URL.
+    #[serde(default)]
+    pub start: Option<Duration>,
+    #[serde(default)]
+    pub requester: Option<Requester>,
+    /// When the song was added to the queue.
+    #[serde(default)]
+    pub queued_at: Option<Timestamp>,
+}
+
+impl Song {
+    /// Marks the song as queued right now by the given user.
+    pub fn requested_by(mut self, requester: Requester) -> Self {
+        self.requester = Some(requester);
+        self.queued_at = Some(Timestamp::now());
+        self
+    }
+}
+
+/// Song with nothing but a title and a URL made from it, for the tests.
+#[cfg(test)]
+pub(crate) fn song(title: &str) -> Song {
+    Song {
+        title: title.to_string(),
+        url: format!("https://example.com/{title}"),
+        duration: None,
+        thumbnail: None,
+        start: None,
+        requester: None,
+        queued_at: None,
+    }
+}
+
+/// User who added a song to the queue.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Requester {
+    pub user_id: UserId,
+    /// Name shown in the server when the song was queued, so it can be displayed without fetching the member.
+    pub display_name: String,
 }
 
+/// Volume of the tracks, as a percentage of their original volume.
+pub const DEFAULT_VOLUME: u32 = 100;
+pub const MAX_VOLUME: u32 = 200;
+
+/// Songs a single request can add to the queue, unless the server changes it.
+pub const DEFAULT_MAX_PLAYLIST_SONGS: usize = 500;
+
+/// Percentage of the listeners whose votes are needed to skip a track, when vote skipping is enabled.
+pub const DEFAULT_SKIP_THRESHOLD: u32 = 50;
+
 pub struct ServerData {
     pub track_handle: Option<TrackHandle>,
-    pub queue: VecDeque<Song>,
+    /// Whether the next song is being started, which takes a few seconds while yt-dlp loads it.
+    pub is_starting: bool,
+    pub current_song: Option<Song>,
+    /// Text channel where the current song was announced.
+    pub channel_id: Option<ChannelId>,
+    pub queue: GuildQueue,
+    /// Songs played before the current one, used to go back to them.
+    pub history: PlayHistory,
+    pub repeat_mode: RepeatMode,
+    pub volume: u32,
+    /// Percentage of votes needed to skip, or None if anyone can skip on their own.
+    pub skip_threshold: Option<u32>,
+    pub skip_votes: SkipVotes,
+    /// Role needed to run the commands that change the queue of everyone, if any.
+    pub dj_role: Option<RoleId>,
+    /// Songs a single request can add to the queue, mostly to cap huge playlists.
+    pub max_playlist_songs: usize,
+    pub idle: IdleState,
+}
+
+impl Default for ServerData {
+    fn default() -> Self {
+        Self {
+            track_handle: None,
+            is_starting: false,
+            current_song: None,
+            channel_id: None,
+            queue: GuildQueue::default(),
+            history: PlayHistory::default(),
+            repeat_mode: RepeatMode::default(),
+            volume: DEFAULT_VOLUME,
+            skip_threshold: None,
+            skip_votes: SkipVotes::default(),
+            dj_role: None,
+            max_playlist_songs: DEFAULT_MAX_PLAYLIST_SONGS,
+            idle: IdleState::default(),
+        }
+    }
+}
+
+impl ServerData {
+    pub fn is_playing_or_starting(&self) -> bool {
+        self.track_handle.is_some() || self.is_starting
+    }
+
+    /// Claims the start of the next song, so a single task starts it. Returns false if a song is already playing or
+    /// being started.
+    pub fn claim_start(&mut self) -> bool {
+        if self.is_playing_or_starting() {
+            return false;
+        }
+
+        self.is_starting = true;
+
+        true
+    }
 }
 
 pub struct DubaServers {
     pub servers: HashMap<u64, ServerData>,
-}
\ No newline at end of file
+    /// State restored from disk on start up, waiting for someone to resume it.
+    pub resumable: HashMap<u64, ServerSnapshot>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_one_start_is_claimed_at_a_time() {
+        let mut server = ServerData::default();
+
+        assert!(!server.is_playing_or_starting());
+        assert!(server.claim_start());
+        assert!(server.is_playing_or_starting());
+        assert!(!server.claim_start());
+
+        // Once the start is over, whether it failed or the track is set, the next one can be claimed
+        server.is_starting = false;
+
+        assert!(server.claim_start());
+    }
+}
@@ -0,0 +1,54 @@
+use serenity::client::Context;
+use serenity::framework::standard::{Args, CommandOptions, Reason};
+use serenity::framework::standard::macros::check;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, UserId};
+
+use crate::{get_guild, get_server, get_voice_channel_listeners};
+
+pub const NOT_A_DJ_MESSAGE: &str = "Only the DJ role, admins or someone alone with the bot can do that";
+
+/// Commands that change the queue of everyone, so they are restricted to DJs.
+pub const DJ_COMMANDS: [&str; 7] = ["stop", "shuffle", "goto", "previous", "remove", "move", "swap"];
+
+#[check]
+#[name = "DJ"]
+async fn dj_check(ctx: &Context, msg: &Message, _: &mut Args, _: &CommandOptions) -> Result<(), Reason> {
+    let guild_id = msg.guild_id.ok_or(Reason::Log("Guild not found".to_string()))?;
+
+    if is_dj(ctx, &guild_id, msg.author.id).await {
+        Ok(())
+    } else {
+        Err(Reason::User(NOT_A_DJ_MESSAGE.to_string()))
+    }
+}
+
+/// Whether the user can run the DJ commands: anyone can if the guild has no DJ role, otherwise only members with the
+/// role, admins, or a user that is alone with the bot.
+pub async fn is_dj(ctx: &Context, guild_id: &GuildId, user_id: UserId) -> bool {
+    let dj_role = {
+        let data = ctx.data.read().await;
+
+        match get_server(&data, guild_id).and_then(|server| server.dj_role) {
+            Some(dj_role) => dj_role,
+            None => return true,
+        }
+    };
+
+    if get_voice_channel_listeners(ctx, guild_id) == [user_id] {
+        return true;
+    }
+
+    let member = match guild_id.member(ctx, user_id).await {
+        Ok(member) => member,
+        Err(_) => return false,
+    };
+
+    if member.roles.contains(&dj_role) {
+        return true;
+    }
+
+    get_guild(ctx, guild_id)
+        .map(|guild| guild.member_permissions(&member).administrator())
+        .unwrap_or(false)
+}
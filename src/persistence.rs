@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serenity::framework::standard::CommandError;
+use serenity::model::id::{ChannelId, RoleId};
+use serenity::prelude::{RwLock, TypeMap};
+use songbird::tracks::TrackHandle;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::models::{DEFAULT_MAX_PLAYLIST_SONGS, DEFAULT_VOLUME, DubaServers, ServerData, Song};
+use crate::queue::RepeatMode;
+use crate::ServersManager;
+
+const DEFAULT_STATE_FILE: &str = "duba_state.json";
+
+/// State of a server that is written to disk, so it can be resumed after a restart.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ServerSnapshot {
+    pub current_song: Option<Song>,
+    pub position: Option<Duration>,
+    pub queue: Vec<Song>,
+    pub channel_id: Option<ChannelId>,
+    #[serde(default)]
+    pub repeat_mode: RepeatMode,
+    #[serde(default = "default_volume")]
+    pub volume: u32,
+    #[serde(default)]
+    pub skip_threshold: Option<u32>,
+    #[serde(default)]
+    pub dj_role: Option<RoleId>,
+    #[serde(default = "default_max_playlist_songs")]
+    pub max_playlist_songs: usize,
+}
+
+fn default_volume() -> u32 {
+    DEFAULT_VOLUME
+}
+
+fn default_max_playlist_songs() -> usize {
+    DEFAULT_MAX_PLAYLIST_SONGS
+}
+
+impl ServerSnapshot {
+    pub fn is_empty(&self) -> bool {
+        self.current_song.is_none() && self.queue.is_empty()
+    }
+
+    pub fn has_default_settings(&self) -> bool {
+        self.repeat_mode == RepeatMode::default() && self.volume == DEFAULT_VOLUME && self.skip_threshold.is_none()
+            && self.dj_role.is_none()
+            && self.max_playlist_songs == DEFAULT_MAX_PLAYLIST_SONGS
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len() + usize::from(self.current_song.is_some())
+    }
+
+    /// Songs to be queued again when resuming, starting with the one that was playing from where it was left.
+    pub fn into_songs(self) -> Vec<Song> {
+        let position = self.position;
+
+        self.current_song
+            .map(|mut song| {
+                song.start = position.or(song.start);
+                song
+            })
+            .into_iter()
+            .chain(self.queue)
+            .collect()
+    }
+}
+
+impl ServerData {
+    /// Server with the settings of a snapshot, but none of its songs.
+    pub fn with_settings_of(snapshot: &ServerSnapshot) -> Self {
+        Self {
+            repeat_mode: snapshot.repeat_mode,
+            volume: snapshot.volume,
+            skip_threshold: snapshot.skip_threshold,
+            dj_role: snapshot.dj_role,
+            max_playlist_songs: snapshot.max_playlist_songs,
+            ..Self::default()
+        }
+    }
+
+    /// Snapshot of the server without the position of its current track, which can only be read by waiting on it.
+    fn snapshot(&self) -> ServerSnapshot {
+        ServerSnapshot {
+            current_song: self.current_song.clone(),
+            position: None,
+            queue: self.queue.iter().cloned().collect(),
+            channel_id: self.channel_id,
+            repeat_mode: self.repeat_mode,
+            volume: self.volume,
+            skip_threshold: self.skip_threshold,
+            dj_role: self.dj_role,
+            max_playlist_songs: self.max_playlist_songs,
+        }
+    }
+}
+
+pub struct PersistenceMap;
+
+impl serenity::prelude::TypeMapKey for PersistenceMap {
+    type Value = Arc<Persistence>;
+}
+
+/// Local file whose whole content is replaced on each change.
+///
+/// Contents are numbered when they are taken, which must happen while the data they come from is still locked, and
+/// a content older than the last one written is skipped. Otherwise two saves could take their contents in one order
+/// and write them in the other, leaving the older state on disk.
+pub struct StateFile {
+    path: PathBuf,
+    last_version: AtomicU64,
+    written_version: Mutex<u64>,
+}
+
+impl StateFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            last_version: AtomicU64::new(0),
+            written_version: Mutex::new(0),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Numbers a content that is about to be taken from the locked data.
+    pub fn next_version(&self) -> u64 {
+        self.last_version.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Replaces the content of the file, unless a newer content has already been written. A temporary file is
+    /// written first, so a crash while writing doesn't corrupt the previous content.
+    pub async fn write(&self, version: u64, content: String) -> Result<(), CommandError> {
+        let mut written_version = self.written_version.lock().await;
+
+        if version <= *written_version {
+            return Ok(());
+        }
+
+        let temp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&temp_path, content).await?;
+        tokio::fs::rename(&temp_path, &self.path).await?;
+
+        *written_version = version;
+
+        Ok(())
+    }
+}
+
+/// Local JSON file where the servers state is stored.
+pub struct Persistence {
+    file: StateFile,
+}
+
+impl Persistence {
+    /// Uses the file given by the DUBA_STATE_FILE environment variable, or a file in the working directory if
+    /// it's not set.
+    pub fn from_env() -> Self {
+        let path = env::var("DUBA_STATE_FILE").unwrap_or(DEFAULT_STATE_FILE.to_string());
+
+        Self {
+            file: StateFile::new(PathBuf::from(path)),
+        }
+    }
+
+    /// Reads the state of all the servers, including the ones without songs that only have custom settings.
+    pub fn load(&self) -> HashMap<u64, ServerSnapshot> {
+        let path = self.file.path();
+
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(why) => {
+                info!("No state loaded from {}: {why:?}", path.display());
+
+                return HashMap::new();
+            }
+        };
+
+        match serde_json::from_str::<HashMap<u64, ServerSnapshot>>(&content) {
+            Ok(snapshots) => snapshots,
+            Err(why) => {
+                info!("Invalid state file {}: {why:?}", path.display());
+
+                HashMap::new()
+            }
+        }
+    }
+
+    async fn save(&self, version: u64, snapshots: &HashMap<u64, ServerSnapshot>) -> Result<(), CommandError> {
+        let json = serde_json::to_string(snapshots)?;
+
+        self.file.write(version, json).await
+    }
+}
+
+/// Snapshots of the servers to write, along with the tracks whose position is still to be read.
+fn snapshot_servers(duba_servers: &DubaServers) -> (HashMap<u64, ServerSnapshot>, Vec<(u64, TrackHandle)>) {
+    let mut snapshots = HashMap::new();
+    let mut playing_tracks = Vec::new();
+
+    for (guild_id, server) in &duba_servers.servers {
+        let mut snapshot = server.snapshot();
+
+        // Songs that haven't been resumed yet are kept, otherwise they would be lost on the first change
+        if snapshot.is_empty() {
+            if let Some(resumable) = duba_servers.resumable.get(guild_id) {
+                snapshot.current_song = resumable.current_song.clone();
+                snapshot.position = resumable.position;
+                snapshot.queue = resumable.queue.clone();
+                snapshot.channel_id = resumable.channel_id;
+            }
+        }
+
+        if !snapshot.is_empty() || !snapshot.has_default_settings() {
+            if let Some(track_handle) = &server.track_handle {
+                playing_tracks.push((*guild_id, track_handle.clone()));
+            }
+
+            snapshots.insert(*guild_id, snapshot);
+        }
+    }
+
+    (snapshots, playing_tracks)
+}
+
+/// Writes the current state of all the servers to disk. Failures are only logged, as losing the state should never
+/// stop the bot from playing.
+pub async fn save_servers(data: &RwLock<TypeMap>) {
+    let persistence: Arc<Persistence>;
+    let version: u64;
+    let mut snapshots: HashMap<u64, ServerSnapshot>;
+    let playing_tracks: Vec<(u64, TrackHandle)>;
+
+    {
+        let data = data.read().await;
+
+        persistence = match data.get::<PersistenceMap>() {
+            Some(persistence) => persistence.clone(),
+            None => return,
+        };
+
+        version = persistence.file.next_version();
+
+        (snapshots, playing_tracks) = match data.get::<ServersManager>() {
+            Some(duba_servers) => snapshot_servers(duba_servers),
+            None => return,
+        };
+    }
+
+    // Positions are read once the lock is released, so the commands waiting to change the data aren't held up
+    for (guild_id, track_handle) in playing_tracks {
+        if let Some(snapshot) = snapshots.get_mut(&guild_id) {
+            snapshot.position = track_handle.get_info().await.ok().map(|info| info.position);
+        }
+    }
+
+    if let Err(why) = persistence.save(version, &snapshots).await {
+        info!("Error saving state: {why:?}");
+    }
+}
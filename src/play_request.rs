@@ -0,0 +1,107 @@
+use crate::errors::DubaError;
+use crate::playlists::PlaylistOptions;
+
+/// Input of the play commands: what to play and how.
+pub struct PlayRequest {
+    /// URL or search terms.
+    pub query: String,
+    /// Queues the whole playlist of a video opened from one, instead of just the video.
+    pub whole_playlist: bool,
+    pub playlist: PlaylistOptions,
+}
+
+impl PlayRequest {
+    pub fn new(query: &str) -> Self {
+        Self {
+            query: query.trim().to_string(),
+            whole_playlist: false,
+            playlist: PlaylistOptions::default(),
+        }
+    }
+
+    /// Reads the flags mixed with the query of prefixed commands, e.g. `!play <url> --from 10 --to 40 --shuffle`.
+    pub fn parse(input: &str) -> Result<Self, DubaError> {
+        let mut query_words: Vec<&str> = Vec::new();
+        let mut whole_playlist = false;
+        let mut playlist = PlaylistOptions::default();
+        let mut words = input.split_whitespace();
+
+        while let Some(word) = words.next() {
+            match word {
+                "--playlist" => whole_playlist = true,
+                "--shuffle" => playlist.shuffle = true,
+                "--reverse" => playlist.reverse = true,
+                "--from" => playlist.from = Some(parse_position(word, words.next())?),
+                "--to" => playlist.to = Some(parse_position(word, words.next())?),
+                "--limit" => playlist.limit = Some(parse_position(word, words.next())?),
+                _ => query_words.push(word),
+            }
+        }
+
+        playlist.check_range()?;
+
+        Ok(Self {
+            query: query_words.join(" "),
+            whole_playlist,
+            playlist,
+        })
+    }
+}
+
+/// Reads the value of a numeric option, which must be at least 1.
+fn parse_position(option: &str, value: Option<&str>) -> Result<usize, DubaError> {
+    value
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|value| *value >= 1)
+        .ok_or(DubaError::InvalidPlayOption { option: option.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_query_between_the_flags() {
+        let request = PlayRequest::parse("never gonna --shuffle give you up --playlist").expect("request should be parsed");
+
+        assert_eq!(request.query, "never gonna give you up");
+        assert!(request.whole_playlist);
+        assert!(request.playlist.shuffle);
+        assert!(!request.playlist.reverse);
+    }
+
+    #[test]
+    fn reads_the_playlist_range_and_limit() {
+        let request = PlayRequest::parse("https://example.com/list --from 10 --to 40 --limit 5 --reverse")
+            .expect("request should be parsed");
+
+        assert_eq!(request.query, "https://example.com/list");
+        assert_eq!(request.playlist.from, Some(10));
+        assert_eq!(request.playlist.to, Some(40));
+        assert_eq!(request.playlist.limit, Some(5));
+        assert!(request.playlist.reverse);
+        assert!(!request.playlist.shuffle);
+    }
+
+    #[test]
+    fn accepts_single_entry_ranges() {
+        let request = PlayRequest::parse("https://example.com/list --from 3 --to 3").expect("request should be parsed");
+
+        assert_eq!(request.playlist.playlist_items().as_deref(), Some("3:3"));
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        let cases = [
+            ("url --from", "invalid_play_option"),
+            ("url --from 0", "invalid_play_option"),
+            ("url --to -1", "invalid_play_option"),
+            ("url --limit ten", "invalid_play_option"),
+            ("url --from 10 --to 3", "invalid_playlist_range"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(PlayRequest::parse(input).err().map(|why| why.kind()), Some(expected), "{input}");
+        }
+    }
+}
@@ -1,88 +1,376 @@
-use std::process::Command;
-use std::time::Duration;
+use std::fmt;
+use std::process::Stdio;
 
 use serde::{Deserialize, Serialize};
+use serde_json::error::Category;
 use serenity::framework::standard::CommandError;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tracing::info;
 
+use crate::errors::DubaError;
 use crate::models::Song;
+use crate::timestamps::duration_from_seconds;
 
+/// Entry of a playlist as listed by `yt-dlp -j --flat-playlist`. Only the fields that every extractor provides are
+/// required, the rest vary between sites.
 #[derive(Serialize, Deserialize)]
 pub struct PlaylistSong {
-    #[serde(rename = "_type")]
-    pub type_field: String,
-    #[serde(rename = "ie_key")]
-    pub ie_key: String,
     pub id: String,
     pub url: String,
     pub title: String,
+    #[serde(rename = "_type")]
+    pub type_field: Option<String>,
+    pub ie_key: Option<String>,
+    /// Page of the entry. Some extractors (e.g. SoundCloud) give an API URL in `url` and the page only here.
+    pub webpage_url: Option<String>,
     pub description: Option<String>,
-    pub duration: Option<i64>,
-    #[serde(rename = "playlist_count")]
-    pub playlist_count: i64,
-    pub playlist: String,
-    #[serde(rename = "playlist_id")]
-    pub playlist_id: String,
-    #[serde(rename = "playlist_title")]
-    pub playlist_title: String,
-    #[serde(rename = "n_entries")]
-    pub n_entries: i64,
-    #[serde(rename = "playlist_index")]
-    pub playlist_index: i64,
+    /// Length in seconds, fractional for some videos and missing for live streams.
+    pub duration: Option<f64>,
+    /// `is_live` for ongoing streams, whose duration is unknown even if reported.
+    pub live_status: Option<String>,
+    pub thumbnail: Option<String>,
+    pub playlist_count: Option<i64>,
+    pub playlist: Option<String>,
+    pub playlist_id: Option<String>,
+    pub playlist_title: Option<String>,
+    pub n_entries: Option<i64>,
+    pub playlist_index: Option<i64>,
     #[serde(rename = "__last_playlist_index")]
-    pub last_playlist_index: i64,
-    #[serde(rename = "playlist_autonumber")]
-    pub playlist_autonumber: i64,
-    pub epoch: i64,
-    #[serde(rename = "duration_string")]
-    pub duration_string: String,
+    pub last_playlist_index: Option<i64>,
+    pub playlist_autonumber: Option<i64>,
+    pub epoch: Option<i64>,
+    pub duration_string: Option<String>,
+}
+
+/// Why an entry of a playlist could not be queued.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SkipReason {
+    /// The line printed by yt-dlp is not valid JSON.
+    Unreadable,
+    /// The entry lacks an ID, URL or title.
+    MissingFields,
+    /// The entry is a private or deleted video.
+    Unavailable,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            SkipReason::Unreadable => "unreadable",
+            SkipReason::MissingFields => "incomplete",
+            SkipReason::Unavailable => "private or deleted",
+        };
+
+        write!(f, "{reason}")
+    }
+}
+
+/// Entry of a playlist, which is either a song that can be queued or the reason why it has been skipped.
+pub type PlaylistEntry = Result<Song, SkipReason>;
+
+/// Part of a playlist to queue, and in which order.
+#[derive(Clone, Default)]
+pub struct PlaylistOptions {
+    /// 1-based index of the first entry.
+    pub from: Option<usize>,
+    /// 1-based index of the last entry, included.
+    pub to: Option<usize>,
+    /// Maximum number of songs to queue.
+    pub limit: Option<usize>,
+    pub shuffle: bool,
+    pub reverse: bool,
 }
 
-pub fn songs_list_from_playlist_url(url: &str) -> Result<Vec<Song>, CommandError> {
-    println!("Getting songs from playlist {url}");
+impl PlaylistOptions {
+    /// Checks that the range doesn't end before it starts, which yt-dlp would silently treat as empty.
+    pub fn check_range(&self) -> Result<(), DubaError> {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) if from > to => Err(DubaError::InvalidPlaylistRange { from, to }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Value of yt-dlp's `--playlist-items` for the range and order of these options, if any is set. The limit
+    /// narrows the range when the entries are listed in order, so yt-dlp doesn't list more of them than needed.
+    pub fn playlist_items(&self) -> Option<String> {
+        let last = match self.limit {
+            // Shuffled and reversed playlists are read whole, the limit then applies to the songs they yield
+            Some(limit) if !self.shuffle && !self.reverse => {
+                let last = self.from.unwrap_or(1).saturating_add(limit.saturating_sub(1));
+                Some(self.to.map_or(last, |to| to.min(last)))
+            }
+            _ => self.to,
+        };
+
+        let from = self.from.map(|from| from.to_string()).unwrap_or_default();
+        let to = last.map(|to| to.to_string()).unwrap_or_default();
+
+        if self.reverse {
+            // With a negative step, the range goes from the end to the start
+            Some(format!("{to}:{from}:-1"))
+        } else if self.from.is_some() || last.is_some() {
+            Some(format!("{from}:{to}"))
+        } else {
+            None
+        }
+    }
+}
+
+/// Titles given by YouTube to the entries of a playlist that can't be played anymore.
+const UNAVAILABLE_TITLES: [&str; 2] = ["[Private video]", "[Deleted video]"];
+
+/// Starts yt-dlp for the given playlist and sends each entry through the returned channel as soon as its line
+/// has been read, so the first songs can be played while the rest of the playlist is still being listed.
+pub fn songs_from_playlist_url(url: &str, options: &PlaylistOptions) -> Result<UnboundedReceiver<PlaylistEntry>, CommandError> {
+    info!("Getting songs from playlist {url}");
+
+    let mut command = Command::new("yt-dlp");
+    command.arg("-j").arg("--flat-playlist");
+
+    if let Some(playlist_items) = options.playlist_items() {
+        command.arg("--playlist-items").arg(playlist_items);
+    }
+
+    if options.shuffle {
+        command.arg("--playlist-random");
+    }
 
-    let output = Command::new("yt-dlp")
-        .arg("-j")
-        .arg("--flat-playlist")
+    let mut child = command
         .arg(url)
-        .output()
-        .expect("yt-dlp command failed to start");
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|why| DubaError::YtDlpFailed { reason: format!("command failed to start: {why}") })?;
+
+    let stdout = child.stdout.take().ok_or(DubaError::YtDlpFailed { reason: "stdout not available".to_string() })?;
+    let stderr = child.stderr.take().ok_or(DubaError::YtDlpFailed { reason: "stderr not available".to_string() })?;
+    let (sender, receiver) = unbounded_channel();
+
+    // Warnings are read as they come, otherwise a full stderr pipe would block yt-dlp before it lists everything
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            info!("yt-dlp: {line}");
+        }
+    });
 
-    let error = String::from_utf8(output.stderr).map_err(|_| CommandError::from("Error reading stderr"))?;
-    let result = String::from_utf8(output.stdout).map_err(|_| CommandError::from("Error reading stdout"))?;
+    let url = url.to_string();
 
-    println!("Error: {error}");
-    println!("Result: {result}");
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
 
-    if !result.is_empty() {
-        let lines: Vec<&str> = result.split('\n').collect();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.is_empty() {
+                continue;
+            }
 
-        let playlist_songs = lines
-            .iter()
-            .filter_map(|line| {
-                let playlist_song: PlaylistSong = serde_json::from_str(line).ok()?;
+            // The receiver is gone, there is no point in reading the rest of the playlist
+            if sender.send(song_from_playlist_line(&line)).is_err() {
+                return;
+            }
+        }
 
-                let duration: Option<Duration> = playlist_song.duration
-                    .and_then(|duration| {
-                        let d: u64 = duration.try_into().ok()?;
-                        Some(Duration::from_nanos(d))
-                    });
+        match child.wait().await {
+            Ok(status) if !status.success() => info!("yt-dlp exited with {status} for playlist {url}"),
+            Ok(_) => {}
+            Err(why) => info!("yt-dlp command failed: {why}"),
+        }
+    });
 
-                let song = Song {
-                    title: playlist_song.title,
-                    url: playlist_song.url,
-                    duration,
-                };
+    Ok(receiver)
+}
 
-                Some(song)
-            })
-            .collect::<Vec<Song>>();
+/// Lists the first results found by YouTube for the given search terms.
+pub async fn songs_from_search(query: &str, count: usize) -> Result<Vec<Song>, CommandError> {
+    // Search results are listed by yt-dlp the same way as the entries of a playlist
+    let mut receiver = songs_from_playlist_url(&format!("ytsearch{count}:{query}"), &PlaylistOptions::default())?;
+    let mut songs = Vec::with_capacity(count);
 
-        if playlist_songs.len() < lines.len() {
-            println!("Some songs have been skips due to errors during parsing");
+    while let Some(entry) = receiver.recv().await {
+        if let Ok(song) = entry {
+            songs.push(song);
         }
+    }
 
-        Ok(playlist_songs)
+    Ok(songs)
+}
+
+fn song_from_playlist_line(line: &str) -> PlaylistEntry {
+    let playlist_song: PlaylistSong = serde_json::from_str(line).map_err(|why| {
+        info!("Skipping playlist entry: {why}");
+
+        match why.classify() {
+            Category::Data => SkipReason::MissingFields,
+            _ => SkipReason::Unreadable,
+        }
+    })?;
+
+    if UNAVAILABLE_TITLES.contains(&playlist_song.title.as_str()) {
+        return Err(SkipReason::Unavailable);
+    }
+
+    let is_youtube = playlist_song.ie_key.as_deref() == Some("Youtube");
+
+    let duration = if playlist_song.live_status.as_deref() == Some("is_live") {
+        None
+    } else {
+        duration_from_seconds(playlist_song.duration)
+    };
+
+    // Flat playlists don't include thumbnails, but YouTube ones can be built from the video ID
+    let thumbnail = if is_youtube {
+        Some(format!("https://i.ytimg.com/vi/{}/hqdefault.jpg", playlist_song.id))
     } else {
-        Err(CommandError::from(error))
+        playlist_song.thumbnail
+    };
+
+    let url = match playlist_song.webpage_url {
+        Some(webpage_url) if webpage_url.starts_with("http") => webpage_url,
+        // Some versions of yt-dlp only give the video ID of YouTube entries
+        _ if is_youtube && !playlist_song.url.starts_with("http") => {
+            format!("https://www.youtube.com/watch?v={}", playlist_song.id)
+        }
+        _ => playlist_song.url,
+    };
+
+    Ok(Song {
+        title: playlist_song.title,
+        url,
+        duration,
+        thumbnail,
+        start: None,
+        requester: None,
+        queued_at: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Entry printed by `yt-dlp -j --flat-playlist` for a YouTube playlist.
+    const YOUTUBE_ENTRY: &str = r#"{"_type": "url", "ie_key": "Youtube", "id": "dQw4w9WgXcQ", "url": "https://www.youtube.com/watch?v=dQw4w9WgXcQ", "title": "Rick Astley - Never Gonna Give You Up (Official Music Video)", "description": null, "duration": 212.0, "channel_id": "UCuAXFkgsw1L7xaCfnd5JJOw", "channel": "Rick Astley", "uploader": "Rick Astley", "timestamp": null, "release_timestamp": null, "availability": null, "view_count": 1500000000, "live_status": null, "webpage_url": "https://www.youtube.com/watch?v=dQw4w9WgXcQ", "original_url": "https://www.youtube.com/watch?v=dQw4w9WgXcQ", "webpage_url_basename": "watch", "webpage_url_domain": "youtube.com", "extractor": "youtube", "extractor_key": "Youtube", "playlist_count": 3, "playlist": "Duba test", "playlist_id": "PLduba", "playlist_title": "Duba test", "n_entries": 3, "playlist_index": 1, "__last_playlist_index": 3, "playlist_autonumber": 1, "epoch": 1700000000, "duration_string": "3:32", "release_year": null}"#;
+
+    /// Entry of a video whose length has a fractional part.
+    const FRACTIONAL_ENTRY: &str = r#"{"_type": "url", "ie_key": "Youtube", "id": "jNQXAC9IVRw", "url": "https://www.youtube.com/watch?v=jNQXAC9IVRw", "title": "Me at the zoo", "description": null, "duration": 19.44, "live_status": null, "playlist_count": 3, "playlist": "Duba test", "playlist_id": "PLduba", "playlist_title": "Duba test", "n_entries": 3, "playlist_index": 2, "__last_playlist_index": 3, "playlist_autonumber": 2, "epoch": 1700000000, "duration_string": "0:19"}"#;
+
+    /// Entry of an ongoing live stream, which has no duration.
+    const LIVE_ENTRY: &str = r#"{"_type": "url", "ie_key": "Youtube", "id": "jfKfPfyJRdk", "url": "https://www.youtube.com/watch?v=jfKfPfyJRdk", "title": "lofi hip hop radio - beats to relax/study to", "description": null, "duration": null, "live_status": "is_live", "playlist_count": 3, "playlist": "Duba test", "playlist_id": "PLduba", "playlist_title": "Duba test", "n_entries": 3, "playlist_index": 3, "__last_playlist_index": 3, "playlist_autonumber": 3, "epoch": 1700000000}"#;
+
+    /// Entry of a SoundCloud set, whose `url` is an API URL.
+    const SOUNDCLOUD_ENTRY: &str = r#"{"_type": "url", "ie_key": "Soundcloud", "id": "123456789", "url": "https://api.soundcloud.com/tracks/123456789", "title": "Track Name", "duration": 245.123, "thumbnail": "https://i1.sndcdn.com/artworks-000-t500x500.jpg", "webpage_url": "https://soundcloud.com/artist/track-name", "playlist": "Set", "playlist_index": 1}"#;
+
+    /// Entry of a Bandcamp album, without the playlist fields of YouTube.
+    const BANDCAMP_ENTRY: &str = r#"{"_type": "url", "id": "first-track", "url": "https://artist.bandcamp.com/track/first-track", "title": "First Track"}"#;
+
+    /// Entry of a YouTube playlist printed by older versions of yt-dlp, which only give the video ID as URL.
+    const YOUTUBE_ID_ENTRY: &str = r#"{"_type": "url", "ie_key": "Youtube", "id": "dQw4w9WgXcQ", "url": "dQw4w9WgXcQ", "title": "Rick Astley - Never Gonna Give You Up (Official Music Video)", "duration": 212}"#;
+
+    /// Entry of a video that has been made private since it was added to the playlist.
+    const PRIVATE_ENTRY: &str = r#"{"_type": "url", "ie_key": "Youtube", "id": "aaaaaaaaaaa", "url": "https://www.youtube.com/watch?v=aaaaaaaaaaa", "title": "[Private video]", "duration": null, "playlist_index": 4}"#;
+
+    #[test]
+    fn parses_whole_seconds() {
+        let song = song_from_playlist_line(YOUTUBE_ENTRY).expect("entry should be parsed");
+
+        assert_eq!(song.title, "Rick Astley - Never Gonna Give You Up (Official Music Video)");
+        assert_eq!(song.url, "https://www.youtube.com/watch?v=dQw4w9WgXcQ");
+        assert_eq!(song.duration, Some(Duration::from_secs(212)));
+    }
+
+    #[test]
+    fn parses_fractional_seconds() {
+        let song = song_from_playlist_line(FRACTIONAL_ENTRY).expect("entry should be parsed");
+
+        assert_eq!(song.duration, Some(Duration::from_millis(19_440)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn live_streams_have_no_duration() {
+        let song = song_from_playlist_line(LIVE_ENTRY).expect("entry should be parsed");
+
+        assert_eq!(song.duration, None);
+    }
+
+    #[test]
+    fn prefers_the_page_of_the_entry() {
+        let song = song_from_playlist_line(SOUNDCLOUD_ENTRY).expect("entry should be parsed");
+
+        assert_eq!(song.url, "https://soundcloud.com/artist/track-name");
+        assert_eq!(song.duration, Some(Duration::from_millis(245_123)));
+        assert_eq!(song.thumbnail.as_deref(), Some("https://i1.sndcdn.com/artworks-000-t500x500.jpg"));
+    }
+
+    #[test]
+    fn parses_entries_without_playlist_fields() {
+        let song = song_from_playlist_line(BANDCAMP_ENTRY).expect("entry should be parsed");
+
+        assert_eq!(song.title, "First Track");
+        assert_eq!(song.url, "https://artist.bandcamp.com/track/first-track");
+        assert_eq!(song.duration, None);
+    }
+
+    #[test]
+    fn builds_youtube_urls_from_ids() {
+        let song = song_from_playlist_line(YOUTUBE_ID_ENTRY).expect("entry should be parsed");
+
+        assert_eq!(song.url, "https://www.youtube.com/watch?v=dQw4w9WgXcQ");
+    }
+
+    #[test]
+    fn skips_unavailable_videos() {
+        assert_eq!(song_from_playlist_line(PRIVATE_ENTRY).err(), Some(SkipReason::Unavailable));
+    }
+
+    #[test]
+    fn skips_incomplete_entries() {
+        let entry = r#"{"_type": "url", "id": "123", "url": "https://example.com/123"}"#;
+
+        assert_eq!(song_from_playlist_line(entry).err(), Some(SkipReason::MissingFields));
+    }
+
+    #[test]
+    fn skips_unreadable_lines() {
+        assert_eq!(song_from_playlist_line("WARNING: not json").err(), Some(SkipReason::Unreadable));
+    }
+
+    #[test]
+    fn builds_playlist_items() {
+        let range = PlaylistOptions { from: Some(10), to: Some(40), ..PlaylistOptions::default() };
+        let reversed = PlaylistOptions { reverse: true, ..range.clone() };
+        let from_only = PlaylistOptions { from: Some(5), ..PlaylistOptions::default() };
+        let reversed_all = PlaylistOptions { reverse: true, ..PlaylistOptions::default() };
+
+        assert_eq!(PlaylistOptions::default().playlist_items(), None);
+        assert_eq!(range.playlist_items().as_deref(), Some("10:40"));
+        assert_eq!(reversed.playlist_items().as_deref(), Some("40:10:-1"));
+        assert_eq!(from_only.playlist_items().as_deref(), Some("5:"));
+        assert_eq!(reversed_all.playlist_items().as_deref(), Some("::-1"));
+    }
+
+    #[test]
+    fn folds_the_limit_into_playlist_items() {
+        let limited = PlaylistOptions { from: Some(5), limit: Some(10), ..PlaylistOptions::default() };
+        let limit_only = PlaylistOptions { limit: Some(10), ..PlaylistOptions::default() };
+        let within_range = PlaylistOptions { to: Some(8), ..limited.clone() };
+        let shuffled = PlaylistOptions { shuffle: true, ..limited.clone() };
+
+        assert_eq!(limited.playlist_items().as_deref(), Some("5:14"));
+        assert_eq!(limit_only.playlist_items().as_deref(), Some(":10"));
+        assert_eq!(within_range.playlist_items().as_deref(), Some("5:8"));
+        assert_eq!(shuffled.playlist_items().as_deref(), Some("5:"));
+    }
+
+    #[test]
+    fn builds_youtube_thumbnails() {
+        let song = song_from_playlist_line(YOUTUBE_ENTRY).expect("entry should be parsed");
+
+        assert_eq!(song.thumbnail.as_deref(), Some("https://i.ytimg.com/vi/dQw4w9WgXcQ/hqdefault.jpg"));
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::vec_deque::Iter;
+use std::collections::VecDeque;
+use std::fmt;
+
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use serde::{Deserialize, Serialize};
+
+use crate::models::Song;
+
+/// What happens to a song once it has finished playing.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RepeatMode {
+    /// The song is discarded.
+    #[default]
+    Off,
+    /// The song is played again.
+    Track,
+    /// The song is added back at the end of the queue.
+    Queue,
+}
+
+impl RepeatMode {
+    /// Next mode when cycling through them with the loop command.
+    pub fn next(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::Track,
+            RepeatMode::Track => RepeatMode::Queue,
+            RepeatMode::Queue => RepeatMode::Off,
+        }
+    }
+}
+
+impl fmt::Display for RepeatMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RepeatMode::Off => "off",
+            RepeatMode::Track => "track",
+            RepeatMode::Queue => "queue",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Where new songs are added to the queue.
+#[derive(Clone, Copy)]
+pub enum QueuePosition {
+    Back,
+    Front,
+    /// 1-based position, as shown by the queue command.
+    At(usize),
+}
+
+/// Queue of pending songs of a single guild.
+///
+/// It doesn't know anything about Discord, so the commands only have to find the right guild and
+/// call into it.
+#[derive(Default)]
+pub struct GuildQueue {
+    songs: VecDeque<Song>,
+}
+
+impl GuildQueue {
+    pub fn len(&self) -> usize {
+        self.songs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, Song> {
+        self.songs.iter()
+    }
+
+    /// Adds the song at the end of the queue.
+    pub fn enqueue(&mut self, song: Song) {
+        self.songs.push_back(song);
+    }
+
+    /// Adds the song at the top of the queue, so it's the next one to be played.
+    pub fn enqueue_next(&mut self, song: Song) {
+        self.songs.push_front(song);
+    }
+
+    /// Adds all the songs at the end of the queue, keeping their order.
+    pub fn enqueue_all(&mut self, songs: Vec<Song>) {
+        self.songs.extend(songs);
+    }
+
+    /// Adds the song at the given position. Returns false (leaving the queue untouched) if the position is out of
+    /// range.
+    pub fn insert(&mut self, song: Song, position: QueuePosition) -> bool {
+        self.insert_all(vec![song], position)
+    }
+
+    /// Adds the songs at the given position, keeping their order, so the first one ends up at that position.
+    /// Returns false (leaving the queue untouched) if the position is out of range.
+    pub fn insert_all(&mut self, songs: Vec<Song>, position: QueuePosition) -> bool {
+        let index = match position {
+            QueuePosition::Back => self.songs.len(),
+            QueuePosition::Front => 0,
+            QueuePosition::At(index) => {
+                if index == 0 || index > self.songs.len() + 1 {
+                    return false;
+                }
+
+                index - 1
+            }
+        };
+
+        let following_songs = self.songs.split_off(index);
+        self.songs.extend(songs);
+        self.songs.extend(following_songs);
+
+        true
+    }
+
+    /// Moves the position back within the queue if it's past its end, e.g. when the queue has shrunk since the
+    /// position was chosen.
+    pub fn position_within(&self, position: QueuePosition) -> QueuePosition {
+        match position {
+            QueuePosition::At(index) => QueuePosition::At(index.clamp(1, self.songs.len() + 1)),
+            position => position,
+        }
+    }
+
+    /// Removes the songs between the given 1-based indexes, both included. Returns None (leaving the queue
+    /// untouched) if the range is not valid.
+    pub fn remove_range(&mut self, start: usize, end: usize) -> Option<Vec<Song>> {
+        if start == 0 || start > end || end > self.songs.len() {
+            return None;
+        }
+
+        Some(self.songs.drain(start - 1..end).collect())
+    }
+
+    /// Moves the song at the 1-based index `from` so it ends up at the index `to`. Returns the moved song, or None
+    /// (leaving the queue untouched) if any index is out of range.
+    pub fn move_song(&mut self, from: usize, to: usize) -> Option<&Song> {
+        if !self.is_valid_index(from) || !self.is_valid_index(to) {
+            return None;
+        }
+
+        let song = self.songs.remove(from - 1)?;
+        self.songs.insert(to - 1, song);
+
+        self.songs.get(to - 1)
+    }
+
+    /// Swaps the songs at the given 1-based indexes. Returns false (leaving the queue untouched) if any index is out
+    /// of range.
+    pub fn swap(&mut self, first: usize, second: usize) -> bool {
+        if !self.is_valid_index(first) || !self.is_valid_index(second) {
+            return false;
+        }
+
+        self.songs.swap(first - 1, second - 1);
+
+        true
+    }
+
+    fn is_valid_index(&self, index: usize) -> bool {
+        index >= 1 && index <= self.songs.len()
+    }
+
+    /// Removes the next song from the queue and returns it.
+    pub fn advance(&mut self) -> Option<Song> {
+        self.songs.pop_front()
+    }
+
+    /// Puts back a song that has finished playing, according to the repeat mode.
+    pub fn requeue(&mut self, mut song: Song, repeat_mode: RepeatMode) {
+        // Repeated songs are played from the beginning
+        song.start = None;
+
+        match repeat_mode {
+            RepeatMode::Off => {}
+            RepeatMode::Track => self.enqueue_next(song),
+            RepeatMode::Queue => self.enqueue(song),
+        }
+    }
+
+    /// Discards all the songs before the given 1-based index, so the song at that position becomes the next
+    /// one. Returns false (leaving the queue untouched) if the index is out of range.
+    pub fn jump(&mut self, index: usize) -> bool {
+        if index == 0 || index > self.songs.len() {
+            return false;
+        }
+
+        self.songs.drain(..index - 1);
+
+        true
+    }
+
+    pub fn shuffle(&mut self) {
+        self.songs.make_contiguous().shuffle(&mut thread_rng());
+    }
+
+    pub fn clear(&mut self) {
+        self.songs.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::song;
+
+    fn queue_of(titles: &[&str]) -> GuildQueue {
+        let mut queue = GuildQueue::default();
+        queue.enqueue_all(titles.iter().map(|title| song(title)).collect());
+        queue
+    }
+
+    fn titles(queue: &GuildQueue) -> Vec<&str> {
+        queue.iter().map(|song| song.title.as_str()).collect()
+    }
+
+    #[test]
+    fn enqueues_at_both_ends() {
+        let mut queue = queue_of(&["a"]);
+        queue.enqueue(song("b"));
+        queue.enqueue_next(song("c"));
+
+        assert_eq!(titles(&queue), ["c", "a", "b"]);
+        assert_eq!(queue.advance().map(|song| song.title), Some("c".to_string()));
+        assert_eq!(titles(&queue), ["a", "b"]);
+    }
+
+    #[test]
+    fn advances_until_empty() {
+        let mut queue = queue_of(&["a"]);
+
+        assert!(queue.advance().is_some());
+        assert!(queue.advance().is_none());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn requeues_according_to_the_repeat_mode() {
+        let cases = [
+            (RepeatMode::Off, vec!["a", "b"]),
+            (RepeatMode::Track, vec!["x", "a", "b"]),
+            (RepeatMode::Queue, vec!["a", "b", "x"]),
+        ];
+
+        for (repeat_mode, expected) in cases {
+            let mut queue = queue_of(&["a", "b"]);
+            let mut finished = song("x");
+            finished.start = Some(std::time::Duration::from_secs(30));
+
+            queue.requeue(finished, repeat_mode);
+
+            assert_eq!(titles(&queue), expected, "repeat mode {repeat_mode}");
+            assert!(queue.iter().all(|song| song.start.is_none()), "repeat mode {repeat_mode}");
+        }
+    }
+
+    #[test]
+    fn inserts_at_positions() {
+        let cases = [
+            (QueuePosition::Back, Some(vec!["a", "b", "x"])),
+            (QueuePosition::Front, Some(vec!["x", "a", "b"])),
+            (QueuePosition::At(1), Some(vec!["x", "a", "b"])),
+            (QueuePosition::At(2), Some(vec!["a", "x", "b"])),
+            (QueuePosition::At(3), Some(vec!["a", "b", "x"])),
+            (QueuePosition::At(0), None),
+            (QueuePosition::At(4), None),
+        ];
+
+        for (position, expected) in cases {
+            let mut queue = queue_of(&["a", "b"]);
+            let is_inserted = queue.insert(song("x"), position);
+
+            match expected {
+                Some(expected) => assert_eq!(titles(&queue), expected),
+                None => {
+                    assert!(!is_inserted);
+                    assert_eq!(titles(&queue), ["a", "b"]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inserts_batches_in_order() {
+        let cases = [
+            (QueuePosition::Back, vec!["a", "b", "x", "y"]),
+            (QueuePosition::Front, vec!["x", "y", "a", "b"]),
+            (QueuePosition::At(2), vec!["a", "x", "y", "b"]),
+        ];
+
+        for (position, expected) in cases {
+            let mut queue = queue_of(&["a", "b"]);
+
+            assert!(queue.insert_all(vec![song("x"), song("y")], position));
+            assert_eq!(titles(&queue), expected);
+        }
+    }
+
+    #[test]
+    fn moves_positions_within_the_queue() {
+        let queue = queue_of(&["a", "b"]);
+
+        assert!(matches!(queue.position_within(QueuePosition::At(2)), QueuePosition::At(2)));
+        assert!(matches!(queue.position_within(QueuePosition::At(3)), QueuePosition::At(3)));
+        assert!(matches!(queue.position_within(QueuePosition::At(10)), QueuePosition::At(3)));
+        assert!(matches!(queue.position_within(QueuePosition::At(0)), QueuePosition::At(1)));
+        assert!(matches!(queue.position_within(QueuePosition::Front), QueuePosition::Front));
+    }
+
+    #[test]
+    fn removes_ranges() {
+        let cases = [
+            ((1, 1), Some(vec!["b", "c", "d"])),
+            ((2, 3), Some(vec!["a", "d"])),
+            ((1, 4), Some(vec![])),
+            ((0, 1), None),
+            ((3, 2), None),
+            ((3, 5), None),
+        ];
+
+        for ((start, end), expected) in cases {
+            let mut queue = queue_of(&["a", "b", "c", "d"]);
+            let removed = queue.remove_range(start, end);
+
+            match expected {
+                Some(expected) => assert_eq!(titles(&queue), expected, "range {start}-{end}"),
+                None => {
+                    assert!(removed.is_none(), "range {start}-{end}");
+                    assert_eq!(titles(&queue), ["a", "b", "c", "d"]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn moves_songs() {
+        let cases = [
+            ((1, 3), Some(vec!["b", "c", "a"])),
+            ((3, 1), Some(vec!["c", "a", "b"])),
+            ((2, 2), Some(vec!["a", "b", "c"])),
+            ((0, 1), None),
+            ((1, 4), None),
+        ];
+
+        for ((from, to), expected) in cases {
+            let mut queue = queue_of(&["a", "b", "c"]);
+            let moved = queue.move_song(from, to).map(|song| song.title.clone());
+
+            match expected {
+                Some(expected) => {
+                    assert_eq!(moved.as_deref(), Some(expected[to - 1]));
+                    assert_eq!(titles(&queue), expected);
+                }
+                None => {
+                    assert!(moved.is_none());
+                    assert_eq!(titles(&queue), ["a", "b", "c"]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn swaps_songs() {
+        let cases = [
+            ((1, 3), Some(vec!["c", "b", "a"])),
+            ((2, 2), Some(vec!["a", "b", "c"])),
+            ((0, 1), None),
+            ((1, 4), None),
+        ];
+
+        for ((first, second), expected) in cases {
+            let mut queue = queue_of(&["a", "b", "c"]);
+            let is_swapped = queue.swap(first, second);
+
+            assert_eq!(is_swapped, expected.is_some());
+            assert_eq!(titles(&queue), expected.unwrap_or_else(|| vec!["a", "b", "c"]));
+        }
+    }
+
+    #[test]
+    fn jumps_to_the_song_at_the_index() {
+        let cases = [
+            (1, Some(vec!["a", "b", "c"])),
+            (2, Some(vec!["b", "c"])),
+            (3, Some(vec!["c"])),
+            (0, None),
+            (4, None),
+        ];
+
+        for (index, expected) in cases {
+            let mut queue = queue_of(&["a", "b", "c"]);
+            let is_valid = queue.jump(index);
+
+            assert_eq!(is_valid, expected.is_some(), "index {index}");
+            assert_eq!(titles(&queue), expected.unwrap_or_else(|| vec!["a", "b", "c"]), "index {index}");
+        }
+    }
+
+    #[test]
+    fn shuffles_and_clears() {
+        let mut queue = queue_of(&["a", "b", "c"]);
+        queue.shuffle();
+
+        let mut shuffled = titles(&queue);
+        shuffled.sort();
+        assert_eq!(shuffled, ["a", "b", "c"]);
+
+        queue.clear();
+        assert!(queue.is_empty());
+    }
+}
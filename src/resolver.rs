@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serenity::async_trait;
+use serenity::framework::standard::CommandError;
+use songbird::input::{Input, ytdl_search};
+use songbird::ytdl;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::errors::DubaError;
+use crate::models::Song;
+use crate::playlists::{PlaylistEntry, PlaylistOptions, SkipReason, songs_from_playlist_url, songs_from_search};
+use crate::urls::{classify, UrlKind};
+
+const UNKNOWN_TRACK_TITLE: &str = "UNKNOWN TRACK";
+
+/// Turns the user input of the play commands into songs that can be queued.
+#[async_trait]
+pub trait TrackResolver: Send + Sync {
+    /// Resolves a single track from its URL.
+    async fn resolve_url(&self, url: &str) -> Result<Song, CommandError>;
+
+    /// Resolves the first track found for the given search terms.
+    async fn search(&self, query: &str) -> Result<Song, CommandError>;
+
+    /// Lists up to `count` tracks found for the given search terms, best match first.
+    async fn search_results(&self, query: &str, count: usize) -> Result<Vec<Song>, CommandError>;
+
+    /// Lists the entries of a playlist within the range and in the order of the options, including the ones that
+    /// can't be played. Entries are sent as they are found, the channel is closed once the playlist has been read.
+    async fn expand_playlist(&self, url: &str, options: &PlaylistOptions) -> Result<UnboundedReceiver<PlaylistEntry>, CommandError>;
+}
+
+pub struct ResolverMap;
+
+impl serenity::prelude::TypeMapKey for ResolverMap {
+    type Value = Arc<dyn TrackResolver>;
+}
+
+/// Resolver backed by yt-dlp, used by the bot.
+pub struct YtDlpResolver;
+
+#[async_trait]
+impl TrackResolver for YtDlpResolver {
+    async fn resolve_url(&self, url: &str) -> Result<Song, CommandError> {
+        let input = ytdl(url).await
+            .map_err(|why| DubaError::from_ytdl_output(url, &format!("{why:?}")))?;
+
+        song_from_input(input, url)
+    }
+
+    async fn search(&self, query: &str) -> Result<Song, CommandError> {
+        let input = ytdl_search(query).await
+            .map_err(|why| DubaError::from_ytdl_output(query, &format!("{why:?}")))?;
+
+        song_from_input(input, query)
+    }
+
+    async fn search_results(&self, query: &str, count: usize) -> Result<Vec<Song>, CommandError> {
+        songs_from_search(query, count).await
+    }
+
+    async fn expand_playlist(&self, url: &str, options: &PlaylistOptions) -> Result<UnboundedReceiver<PlaylistEntry>, CommandError> {
+        songs_from_playlist_url(url, options)
+    }
+}
+
+fn song_from_input(input: Input, user_input: &str) -> Result<Song, CommandError> {
+    let metadata = *input.metadata;
+
+    let source_url = metadata.source_url.ok_or(DubaError::TrackNotFound { input: user_input.to_string() })?;
+    let song_name = metadata.title.unwrap_or(UNKNOWN_TRACK_TITLE.to_string());
+
+    Ok(Song {
+        title: song_name,
+        url: source_url,
+        duration: metadata.duration,
+        thumbnail: metadata.thumbnail,
+        start: None,
+        requester: None,
+        queued_at: None,
+    })
+}
+
+/// Reads the songs of a playlist that a single request can queue, counting the entries that had to be skipped.
+pub struct PlaylistReader {
+    entries: UnboundedReceiver<PlaylistEntry>,
+    max_songs: usize,
+    server_max_songs: usize,
+    limit: Option<usize>,
+    songs_count: usize,
+    skipped_entries: HashMap<SkipReason, usize>,
+    is_cut_off: bool,
+}
+
+impl PlaylistReader {
+    /// Starts listing the playlist. At most `server_max_songs` songs are read, or the limit of the options if lower.
+    pub async fn open(
+        resolver: &dyn TrackResolver,
+        url: &str,
+        options: &PlaylistOptions,
+        server_max_songs: usize,
+    ) -> Result<Self, CommandError> {
+        let entries = resolver.expand_playlist(url, options).await?;
+
+        Ok(Self {
+            entries,
+            max_songs: options.limit.map_or(server_max_songs, |limit| limit.min(server_max_songs)),
+            server_max_songs,
+            limit: options.limit,
+            songs_count: 0,
+            skipped_entries: HashMap::new(),
+            is_cut_off: false,
+        })
+    }
+
+    /// Next song that can be queued, skipping the entries that can't. Returns None once the playlist has been read or
+    /// enough songs have been, in which case the reader should be dropped so yt-dlp stops listing the rest.
+    pub async fn next_song(&mut self) -> Option<Song> {
+        while let Some(entry) = self.entries.recv().await {
+            if self.songs_count >= self.max_songs {
+                self.is_cut_off = true;
+                return None;
+            }
+
+            match entry {
+                Ok(song) => {
+                    self.songs_count += 1;
+                    return Some(song);
+                }
+                Err(reason) => *self.skipped_entries.entry(reason).or_default() += 1,
+            }
+        }
+
+        None
+    }
+
+    pub fn skipped_entries(&self) -> &HashMap<SkipReason, usize> {
+        &self.skipped_entries
+    }
+
+    /// Whether songs were left out because of the limit of the server, rather than the one asked for.
+    pub fn reached_server_max(&self) -> bool {
+        self.is_cut_off && self.max_songs == self.server_max_songs && self.limit != Some(self.max_songs)
+    }
+}
+
+/// Finds the songs of a URL or the first result of a search. Only the video is returned for videos opened from a
+/// playlist, while all the songs of a playlist URL are, up to `max_songs`.
+pub async fn resolve_songs(resolver: &dyn TrackResolver, query: &str, max_songs: usize) -> Result<Vec<Song>, CommandError> {
+    let songs = match classify(query) {
+        UrlKind::Search => vec![resolver.search(query).await?],
+        UrlKind::Video => vec![resolver.resolve_url(query).await?],
+        UrlKind::VideoInPlaylist { video_url, .. } | UrlKind::Mix { video_url } => vec![resolver.resolve_url(&video_url).await?],
+        UrlKind::Playlist => {
+            let mut playlist = PlaylistReader::open(resolver, query, &PlaylistOptions::default(), max_songs).await?;
+            let mut songs = Vec::new();
+
+            while let Some(song) = playlist.next_song().await {
+                songs.push(song);
+            }
+
+            songs
+        }
+    };
+
+    Ok(songs)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::models::song;
+
+    const PLAYLIST_URL: &str = "https://www.youtube.com/playlist?list=PLduba";
+    const VIDEO_URL: &str = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+
+    /// Resolver that only knows the songs it has been given, so the code deciding what gets queued can be tested
+    /// without network access. Playlists are listed whole, whatever the options.
+    #[derive(Default)]
+    struct MemoryResolver {
+        tracks: HashMap<String, Song>,
+        searches: HashMap<String, Song>,
+        playlists: HashMap<String, Vec<PlaylistEntry>>,
+    }
+
+    #[async_trait]
+    impl TrackResolver for MemoryResolver {
+        async fn resolve_url(&self, url: &str) -> Result<Song, CommandError> {
+            self.tracks.get(url)
+                .cloned()
+                .ok_or(DubaError::TrackNotFound { input: url.to_string() }.into())
+        }
+
+        async fn search(&self, query: &str) -> Result<Song, CommandError> {
+            self.searches.get(query)
+                .cloned()
+                .ok_or(DubaError::TrackNotFound { input: query.to_string() }.into())
+        }
+
+        async fn search_results(&self, query: &str, count: usize) -> Result<Vec<Song>, CommandError> {
+            Ok(self.searches.get(query).cloned().into_iter().take(count).collect())
+        }
+
+        async fn expand_playlist(&self, url: &str, _options: &PlaylistOptions) -> Result<UnboundedReceiver<PlaylistEntry>, CommandError> {
+            let entries = self.playlists.get(url)
+                .ok_or(DubaError::PlaylistEmpty { url: url.to_string() })?;
+
+            let (sender, receiver) = unbounded_channel();
+
+            for entry in entries {
+                // The receiver is still alive, so sending can't fail
+                let _ = sender.send(entry.clone());
+            }
+
+            Ok(receiver)
+        }
+    }
+
+    /// Playlist of songs `a` to `e`, with an unavailable entry after `b`.
+    fn resolver() -> MemoryResolver {
+        let playlist = vec![
+            Ok(song("a")),
+            Ok(song("b")),
+            Err(SkipReason::Unavailable),
+            Ok(song("c")),
+            Ok(song("d")),
+            Ok(song("e")),
+        ];
+
+        MemoryResolver {
+            tracks: HashMap::from([(VIDEO_URL.to_string(), song("video"))]),
+            searches: HashMap::from([("song a".to_string(), song("a"))]),
+            playlists: HashMap::from([(PLAYLIST_URL.to_string(), playlist)]),
+        }
+    }
+
+    async fn read_titles(playlist: &mut PlaylistReader) -> Vec<String> {
+        let mut titles = Vec::new();
+
+        while let Some(song) = playlist.next_song().await {
+            titles.push(song.title);
+        }
+
+        titles
+    }
+
+    #[tokio::test]
+    async fn reads_playlists_within_the_songs_allowed() {
+        let resolver = resolver();
+        let limited = PlaylistOptions { limit: Some(2), ..PlaylistOptions::default() };
+
+        // (options, server max, titles, skipped entries, reached the server max)
+        let cases = [
+            (PlaylistOptions::default(), 10, vec!["a", "b", "c", "d", "e"], 1, false),
+            (PlaylistOptions::default(), 3, vec!["a", "b", "c"], 1, true),
+            (PlaylistOptions::default(), 5, vec!["a", "b", "c", "d", "e"], 1, false),
+            (limited.clone(), 10, vec!["a", "b"], 0, false),
+            (limited, 1, vec!["a"], 0, true),
+        ];
+
+        for (options, server_max_songs, expected, skipped, reached_server_max) in cases {
+            let mut playlist = PlaylistReader::open(&resolver, PLAYLIST_URL, &options, server_max_songs).await
+                .expect("playlist should be found");
+
+            assert_eq!(read_titles(&mut playlist).await, expected);
+            assert_eq!(playlist.skipped_entries().get(&SkipReason::Unavailable).copied().unwrap_or(0), skipped);
+            assert_eq!(playlist.reached_server_max(), reached_server_max);
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_on_unknown_playlists() {
+        let resolver = resolver();
+        let options = PlaylistOptions::default();
+
+        assert!(PlaylistReader::open(&resolver, "https://www.youtube.com/playlist?list=PLmissing", &options, 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn resolves_the_songs_of_each_kind_of_input() {
+        let resolver = resolver();
+
+        let cases = [
+            ("song a", vec!["a"]),
+            (VIDEO_URL, vec!["video"]),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLduba", vec!["video"]),
+            (PLAYLIST_URL, vec!["a", "b", "c"]),
+        ];
+
+        for (query, expected) in cases {
+            let songs = resolve_songs(&resolver, query, 3).await.expect("songs should be resolved");
+            let titles: Vec<&str> = songs.iter().map(|song| song.title.as_str()).collect();
+
+            assert_eq!(titles, expected, "{query}");
+        }
+
+        assert!(resolve_songs(&resolver, "missing", 3).await.is_err());
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{GuildId, UserId};
+use serenity::prelude::{RwLock, TypeMap};
+use tracing::info;
+
+use crate::errors::DubaError;
+use crate::models::Song;
+use crate::persistence::StateFile;
+
+const DEFAULT_PLAYLISTS_FILE: &str = "duba_playlists.json";
+
+/// Playlists a server or a user can save, and songs in each of them, so the file stays reasonably small.
+const MAX_SAVED_PLAYLISTS: usize = 25;
+const MAX_SAVED_PLAYLIST_SONGS: usize = 500;
+const MAX_PLAYLIST_NAME_LENGTH: usize = 32;
+
+/// Flag of the prefixed playlist command to use the playlists of the user instead of the ones of the server.
+const PERSONAL_FLAG: &str = "--mine";
+
+/// Who a saved playlist belongs to. The playlists of a server are shared by all its members, while users can load
+/// theirs in any server.
+#[derive(Clone, Copy)]
+pub enum PlaylistOwner {
+    Guild(GuildId),
+    User(UserId),
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SavedPlaylist {
+    pub songs: Vec<Song>,
+}
+
+/// Saved playlists of all the servers and users, by name.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SavedPlaylists {
+    #[serde(default)]
+    guilds: HashMap<u64, BTreeMap<String, SavedPlaylist>>,
+    #[serde(default)]
+    users: HashMap<u64, BTreeMap<String, SavedPlaylist>>,
+}
+
+impl SavedPlaylists {
+    fn of(&self, owner: PlaylistOwner) -> Option<&BTreeMap<String, SavedPlaylist>> {
+        match owner {
+            PlaylistOwner::Guild(guild_id) => self.guilds.get(&guild_id.0),
+            PlaylistOwner::User(user_id) => self.users.get(&user_id.0),
+        }
+    }
+
+    fn of_mut(&mut self, owner: PlaylistOwner) -> &mut BTreeMap<String, SavedPlaylist> {
+        match owner {
+            PlaylistOwner::Guild(guild_id) => self.guilds.entry(guild_id.0).or_default(),
+            PlaylistOwner::User(user_id) => self.users.entry(user_id.0).or_default(),
+        }
+    }
+
+    /// Playlists of the owner, sorted by name.
+    pub fn list(&self, owner: PlaylistOwner) -> Vec<(&str, &SavedPlaylist)> {
+        self.of(owner)
+            .map(|playlists| playlists.iter().map(|(name, playlist)| (name.as_str(), playlist)).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, owner: PlaylistOwner, name: &str) -> Option<&SavedPlaylist> {
+        self.of(owner)?.get(name)
+    }
+
+    /// Saves the songs under the name, replacing the playlist with the same name if there's one.
+    pub fn save(&mut self, owner: PlaylistOwner, name: &str, songs: Vec<Song>) -> Result<(), DubaError> {
+        let playlists = self.of_mut(owner);
+
+        if !playlists.contains_key(name) && playlists.len() >= MAX_SAVED_PLAYLISTS {
+            return Err(DubaError::TooManySavedPlaylists { max: MAX_SAVED_PLAYLISTS });
+        }
+
+        if songs.len() > MAX_SAVED_PLAYLIST_SONGS {
+            return Err(DubaError::SavedPlaylistFull { name: name.to_string(), max: MAX_SAVED_PLAYLIST_SONGS });
+        }
+
+        playlists.insert(name.to_string(), SavedPlaylist { songs });
+
+        Ok(())
+    }
+
+    /// Adds the songs at the end of a playlist, creating it if needed. Returns how many songs the playlist has now.
+    pub fn add_songs(&mut self, owner: PlaylistOwner, name: &str, songs: Vec<Song>) -> Result<usize, DubaError> {
+        let playlists = self.of_mut(owner);
+
+        if !playlists.contains_key(name) && playlists.len() >= MAX_SAVED_PLAYLISTS {
+            return Err(DubaError::TooManySavedPlaylists { max: MAX_SAVED_PLAYLISTS });
+        }
+
+        let saved_songs = playlists.get(name).map(|playlist| playlist.songs.len()).unwrap_or(0);
+
+        if saved_songs + songs.len() > MAX_SAVED_PLAYLIST_SONGS {
+            return Err(DubaError::SavedPlaylistFull { name: name.to_string(), max: MAX_SAVED_PLAYLIST_SONGS });
+        }
+
+        let playlist = playlists.entry(name.to_string()).or_default();
+        playlist.songs.extend(songs);
+
+        Ok(playlist.songs.len())
+    }
+
+    /// Deletes a playlist, returning false if the owner had none with that name.
+    pub fn delete(&mut self, owner: PlaylistOwner, name: &str) -> bool {
+        self.of_mut(owner).remove(name).is_some()
+    }
+}
+
+/// What the playlist command should do with a saved playlist.
+pub enum PlaylistAction {
+    /// Saves the current song and the queue.
+    Save,
+    /// Adds the songs of the playlist to the queue.
+    Load,
+    List,
+    Delete,
+    /// Adds a URL or the first result of a search to the playlist.
+    Add { query: String },
+}
+
+/// Input of the playlist command, e.g. `save chill`, `add chill <url> --mine` or `list`.
+pub struct PlaylistRequest {
+    pub action: PlaylistAction,
+    /// Name of the playlist, empty when listing them.
+    pub name: String,
+    /// Whether the playlist belongs to the user rather than to the server.
+    pub personal: bool,
+}
+
+impl PlaylistRequest {
+    pub fn new(action: &str, name: &str, query: &str, personal: bool) -> Result<Self, DubaError> {
+        let query = query.trim();
+
+        let action = match action.to_lowercase().as_str() {
+            "save" => PlaylistAction::Save,
+            "load" => PlaylistAction::Load,
+            "delete" => PlaylistAction::Delete,
+            "add" if !query.is_empty() => PlaylistAction::Add { query: query.to_string() },
+            "list" => {
+                return Ok(Self {
+                    action: PlaylistAction::List,
+                    name: String::new(),
+                    personal,
+                });
+            }
+            _ => return Err(DubaError::InvalidPlaylistCommand),
+        };
+
+        if name.trim().is_empty() {
+            return Err(DubaError::InvalidPlaylistCommand);
+        }
+
+        Ok(Self {
+            action,
+            name: playlist_name(name)?,
+            personal,
+        })
+    }
+
+    /// Reads the input of the prefixed command: the action, the name of the playlist and the query of `add`.
+    pub fn parse(input: &str) -> Result<Self, DubaError> {
+        let personal = input.split_whitespace().any(|word| word == PERSONAL_FLAG);
+        let mut words = input.split_whitespace().filter(|word| *word != PERSONAL_FLAG);
+
+        let action = words.next().unwrap_or_default();
+        let name = words.next().unwrap_or_default();
+        let query = words.collect::<Vec<&str>>().join(" ");
+
+        Self::new(action, name, &query, personal)
+    }
+}
+
+/// Names are case insensitive, and kept short and simple so they can be typed as a single word.
+fn playlist_name(name: &str) -> Result<String, DubaError> {
+    let name = name.trim().to_lowercase();
+
+    let is_valid = !name.is_empty()
+        && name.chars().count() <= MAX_PLAYLIST_NAME_LENGTH
+        && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+
+    if is_valid {
+        Ok(name)
+    } else {
+        Err(DubaError::InvalidPlaylistName { max_length: MAX_PLAYLIST_NAME_LENGTH })
+    }
+}
+
+pub struct SavedPlaylistsMap;
+
+impl serenity::prelude::TypeMapKey for SavedPlaylistsMap {
+    type Value = SavedPlaylists;
+}
+
+pub struct PlaylistsFileMap;
+
+impl serenity::prelude::TypeMapKey for PlaylistsFileMap {
+    type Value = Arc<PlaylistsFile>;
+}
+
+/// Local JSON file where the saved playlists are stored.
+pub struct PlaylistsFile {
+    file: StateFile,
+}
+
+impl PlaylistsFile {
+    /// Uses the file given by the DUBA_PLAYLISTS_FILE environment variable, or a file in the working directory if
+    /// it's not set.
+    pub fn from_env() -> Self {
+        let path = env::var("DUBA_PLAYLISTS_FILE").unwrap_or(DEFAULT_PLAYLISTS_FILE.to_string());
+
+        Self {
+            file: StateFile::new(PathBuf::from(path)),
+        }
+    }
+
+    pub fn load(&self) -> SavedPlaylists {
+        let path = self.file.path();
+
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(why) => {
+                info!("No playlists loaded from {}: {why:?}", path.display());
+
+                return SavedPlaylists::default();
+            }
+        };
+
+        match serde_json::from_str::<SavedPlaylists>(&content) {
+            Ok(playlists) => playlists,
+            Err(why) => {
+                info!("Invalid playlists file {}: {why:?}", path.display());
+
+                SavedPlaylists::default()
+            }
+        }
+    }
+}
+
+/// Writes all the saved playlists to disk. Failures are only logged, the playlists are still available until the
+/// bot is restarted.
+pub async fn save_playlists(data: &RwLock<TypeMap>) {
+    let playlists_file: Arc<PlaylistsFile>;
+    let version: u64;
+    let json: Result<String, serde_json::Error>;
+
+    {
+        let data = data.read().await;
+
+        playlists_file = match data.get::<PlaylistsFileMap>() {
+            Some(playlists_file) => playlists_file.clone(),
+            None => return,
+        };
+
+        version = playlists_file.file.next_version();
+
+        // Serializing right away avoids copying all the playlists to write them once the lock is released
+        json = match data.get::<SavedPlaylistsMap>() {
+            Some(playlists) => serde_json::to_string(playlists),
+            None => return,
+        };
+    }
+
+    let result = match json {
+        Ok(json) => playlists_file.file.write(version, json).await,
+        Err(why) => Err(why.into()),
+    };
+
+    if let Err(why) = result {
+        info!("Error saving playlists: {why:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::song;
+
+    fn songs(count: usize) -> Vec<Song> {
+        (0..count).map(|index| song(&index.to_string())).collect()
+    }
+
+    fn titles(playlists: &SavedPlaylists, owner: PlaylistOwner, name: &str) -> Vec<String> {
+        playlists.get(owner, name)
+            .map(|playlist| playlist.songs.iter().map(|song| song.title.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn parses_playlist_requests() {
+        let request = PlaylistRequest::parse("add Chill never gonna --mine give you up").expect("request should be parsed");
+
+        assert!(matches!(request.action, PlaylistAction::Add { ref query } if query == "never gonna give you up"));
+        assert_eq!(request.name, "chill");
+        assert!(request.personal);
+
+        assert!(matches!(PlaylistRequest::parse("list").map(|request| request.action), Ok(PlaylistAction::List)));
+        assert!(PlaylistRequest::parse("add chill").is_err());
+        assert!(PlaylistRequest::parse("rename chill").is_err());
+    }
+
+    #[test]
+    fn validates_playlist_names() {
+        assert_eq!(playlist_name(" Road_Trip-2 ").ok().as_deref(), Some("road_trip-2"));
+        assert!(playlist_name("").is_err());
+        assert!(playlist_name("no/slashes").is_err());
+        assert!(playlist_name(&"a".repeat(MAX_PLAYLIST_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn replaces_playlists_with_the_same_name() {
+        let mut playlists = SavedPlaylists::default();
+        let owner = PlaylistOwner::Guild(GuildId(1));
+
+        assert!(playlists.save(owner, "chill", vec![song("a"), song("b")]).is_ok());
+        assert!(playlists.save(owner, "chill", vec![song("c")]).is_ok());
+
+        assert_eq!(titles(&playlists, owner, "chill"), ["c"]);
+        assert_eq!(playlists.list(owner).len(), 1);
+    }
+
+    #[test]
+    fn limits_the_number_of_playlists() {
+        let mut playlists = SavedPlaylists::default();
+        let owner = PlaylistOwner::User(UserId(1));
+
+        for index in 0..MAX_SAVED_PLAYLISTS {
+            assert!(playlists.save(owner, &index.to_string(), vec![song("a")]).is_ok());
+        }
+
+        assert!(matches!(playlists.save(owner, "extra", vec![song("a")]), Err(DubaError::TooManySavedPlaylists { .. })));
+        assert!(matches!(playlists.add_songs(owner, "extra", vec![song("a")]), Err(DubaError::TooManySavedPlaylists { .. })));
+
+        // Existing playlists can still be replaced or extended
+        assert!(playlists.save(owner, "0", vec![song("b")]).is_ok());
+        assert_eq!(playlists.add_songs(owner, "0", vec![song("c")]).ok(), Some(2));
+
+        // Other owners have their own limit
+        assert!(playlists.save(PlaylistOwner::User(UserId(2)), "extra", vec![song("a")]).is_ok());
+    }
+
+    #[test]
+    fn limits_the_songs_of_a_playlist() {
+        let mut playlists = SavedPlaylists::default();
+        let owner = PlaylistOwner::Guild(GuildId(1));
+
+        assert!(matches!(
+            playlists.save(owner, "long", songs(MAX_SAVED_PLAYLIST_SONGS + 1)),
+            Err(DubaError::SavedPlaylistFull { .. })
+        ));
+        assert!(playlists.get(owner, "long").is_none());
+
+        assert_eq!(playlists.add_songs(owner, "long", songs(MAX_SAVED_PLAYLIST_SONGS - 1)).ok(), Some(MAX_SAVED_PLAYLIST_SONGS - 1));
+        assert_eq!(playlists.add_songs(owner, "long", vec![song("last")]).ok(), Some(MAX_SAVED_PLAYLIST_SONGS));
+        assert!(matches!(playlists.add_songs(owner, "long", vec![song("extra")]), Err(DubaError::SavedPlaylistFull { .. })));
+        assert_eq!(titles(&playlists, owner, "long").last().map(String::as_str), Some("last"));
+    }
+}
@@ -0,0 +1,440 @@
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommandOption};
+use serenity::client::Context;
+use serenity::framework::standard::CommandError;
+use serenity::model::application::command::{Command, CommandOptionType};
+use serenity::model::application::interaction::application_command::{ApplicationCommandInteraction, CommandDataOption};
+use serenity::model::application::interaction::autocomplete::AutocompleteInteraction;
+use serenity::model::Permissions;
+use serenity::Result as SerenityResult;
+use tracing::info;
+
+use crate::embeds::{MAX_LABEL_LENGTH, truncate};
+use crate::errors::DubaError;
+use crate::invocation::Invocation;
+use crate::models::MAX_VOLUME;
+use crate::permissions::{DJ_COMMANDS, is_dj, NOT_A_DJ_MESSAGE};
+use crate::play_request::PlayRequest;
+use crate::playlists::PlaylistOptions;
+use crate::queue::QueuePosition;
+use crate::saved_playlists::PlaylistRequest;
+use crate::{
+    dj_role_command, get_server, goto_command, help_command, history_command, insert_command, loop_command,
+    max_songs_command, move_command, next_command, np_command, pause_command, play_song_with_reaction, playlist_command,
+    previous_command, queue_command, remove_command, replay_command, resume_command, search_command, seek_command,
+    shuffle_command, stop_command, swap_command, unpause_command, volume_command, vote_skip_command,
+};
+
+/// Discord doesn't allow more choices in an autocomplete response.
+const MAX_AUTOCOMPLETE_CHOICES: usize = 25;
+
+/// Registers the slash commands, replacing the ones registered by a previous version of the bot.
+pub async fn register_commands(ctx: &Context) -> SerenityResult<Vec<Command>> {
+    Command::set_global_application_commands(&ctx.http, |commands| {
+        commands
+            .create_application_command(|c| {
+                guild_command(c, "play", "Plays a track or playlist, or adds it to the queue")
+                    .create_option(query_option);
+                play_options(c)
+            })
+            .create_application_command(|c| {
+                guild_command(c, "pn", "Adds a track to the top of the queue to be played next")
+                    .create_option(query_option);
+                play_options(c)
+            })
+            .create_application_command(|c| {
+                guild_command(c, "search", "Searches a track and lets you pick which result to queue")
+                    .create_option(|o| {
+                        o.name("query")
+                            .description("Title of the track")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+            })
+            .create_application_command(|c| {
+                guild_command(c, "insert", "Adds a track to the queue at the given position")
+                    .create_option(|o| index_option(o, "position", "Position of the track in the queue", true))
+                    .create_option(query_option);
+                play_options(c)
+            })
+            .create_application_command(|c| guild_command(c, "pause", "Pauses the current track"))
+            .create_application_command(|c| guild_command(c, "unpause", "Unpauses the currently paused track"))
+            .create_application_command(|c| guild_command(c, "next", "Plays the next track"))
+            .create_application_command(|c| guild_command(c, "stop", "Stops the current track and clears the queue"))
+            .create_application_command(|c| guild_command(c, "queue", "Shows the queue of tracks"))
+            .create_application_command(|c| guild_command(c, "np", "Shows the current track, its progress and the next one"))
+            .create_application_command(|c| guild_command(c, "previous", "Plays again the previous track"))
+            .create_application_command(|c| guild_command(c, "history", "Shows the last played tracks and who requested them"))
+            .create_application_command(|c| guild_command(c, "replay", "Restarts the current track from the beginning"))
+            .create_application_command(|c| guild_command(c, "shuffle", "Reorders the queue randomly"))
+            .create_application_command(|c| {
+                guild_command(c, "loop", "Cycles the repeat mode between off, current track and whole queue")
+            })
+            .create_application_command(|c| {
+                guild_command(c, "resume", "Queues again the tracks that were pending when the bot was restarted")
+            })
+            .create_application_command(|c| guild_command(c, "help", "Lists the commands"))
+            .create_application_command(|c| {
+                guild_command(c, "seek", "Jumps to a position of the current track")
+                    .create_option(|o| {
+                        o.name("position")
+                            .description("Position as mm:ss, or +SECONDS / -SECONDS from the current one")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+            })
+            .create_application_command(|c| {
+                guild_command(c, "volume", "Sets the volume of the tracks, or shows it if no value is given")
+                    .create_option(|o| {
+                        o.name("volume")
+                            .description("Volume percentage")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(0)
+                            .max_int_value(MAX_VOLUME)
+                            .required(false)
+                    })
+            })
+            .create_application_command(|c| {
+                guild_command(c, "voteskip", "Sets the percentage of listeners that must vote to skip a track")
+                    .default_member_permissions(Permissions::MANAGE_GUILD)
+                    .create_option(|o| {
+                        o.name("percentage")
+                            .description("Percentage of listeners, 0 lets anyone skip on their own")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(0)
+                            .max_int_value(100)
+                            .required(false)
+                    })
+            })
+            .create_application_command(|c| {
+                guild_command(c, "playlist", "Saves the queue as a named playlist, and queues saved playlists")
+                    .create_option(|o| {
+                        playlist_subcommand(o, "save", "Saves the current track and the queue, replacing the playlist with that name")
+                    })
+                    .create_option(|o| playlist_subcommand(o, "load", "Adds the songs of a saved playlist to the queue"))
+                    .create_option(|o| playlist_subcommand(o, "delete", "Deletes a saved playlist"))
+                    .create_option(|o| {
+                        playlist_subcommand(o, "add", "Adds a track, or all the tracks of a playlist URL, to a saved playlist")
+                            .create_sub_option(query_option)
+                    })
+                    .create_option(|o| {
+                        o.name("list")
+                            .description("Lists the saved playlists of the server and yours")
+                            .kind(CommandOptionType::SubCommand)
+                    })
+            })
+            .create_application_command(|c| {
+                guild_command(c, "maxsongs", "Sets how many songs a single request can add to the queue")
+                    .default_member_permissions(Permissions::MANAGE_GUILD)
+                    .create_option(|o| {
+                        o.name("songs")
+                            .description("Number of songs, leave empty to show the current one")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .required(false)
+                    })
+            })
+            .create_application_command(|c| {
+                guild_command(c, "djrole", "Restricts the commands that change the queue of everyone to a role")
+                    .default_member_permissions(Permissions::MANAGE_GUILD)
+                    .create_option(|o| {
+                        o.name("role")
+                            .description("DJ role, leave empty to show the current one")
+                            .kind(CommandOptionType::Role)
+                            .required(false)
+                    })
+                    .create_option(|o| flag_option(o, "off", "Remove the DJ role to let anyone manage the queue"))
+            })
+            .create_application_command(|c| {
+                guild_command(c, "remove", "Removes a track, or a range of tracks, from the queue")
+                    .create_option(|o| index_option(o, "from", "Track to remove, or first track of the range", true))
+                    .create_option(|o| index_option(o, "to", "Last track of the range", false))
+            })
+            .create_application_command(|c| {
+                guild_command(c, "move", "Moves a track of the queue to another position")
+                    .create_option(|o| index_option(o, "from", "Track to move", true))
+                    .create_option(|o| index_option(o, "to", "New position of the track", true))
+            })
+            .create_application_command(|c| {
+                guild_command(c, "swap", "Swaps the positions of two tracks of the queue")
+                    .create_option(|o| index_option(o, "first", "First track", true))
+                    .create_option(|o| index_option(o, "second", "Second track", true))
+            })
+            .create_application_command(|c| {
+                guild_command(c, "goto", "Plays immediately a track of the queue, discarding the previous ones")
+                    .create_option(|o| index_option(o, "index", "Track to play", true))
+            })
+    }).await
+}
+
+fn guild_command<'a>(command: &'a mut CreateApplicationCommand, name: &str, description: &str) -> &'a mut CreateApplicationCommand {
+    command
+        .name(name)
+        .description(description)
+        .dm_permission(false)
+}
+
+fn query_option(option: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+    option
+        .name("query")
+        .description("URL or title of the track")
+        .kind(CommandOptionType::String)
+        .required(true)
+}
+
+/// Options of the play commands besides the query, matching the flags of their prefixed versions.
+fn play_options(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .create_option(|o| flag_option(o, "playlist", "Queue the whole playlist of a video opened from one"))
+        .create_option(|o| position_option(o, "from", "First song of the playlist to queue"))
+        .create_option(|o| position_option(o, "to", "Last song of the playlist to queue"))
+        .create_option(|o| position_option(o, "limit", "Maximum number of songs to queue from the playlist"))
+        .create_option(|o| flag_option(o, "shuffle", "Shuffle the songs of the playlist before queuing them"))
+        .create_option(|o| flag_option(o, "reverse", "Queue the songs of the playlist in reverse order"))
+}
+
+fn flag_option<'a>(
+    option: &'a mut CreateApplicationCommandOption,
+    name: &str,
+    description: &str,
+) -> &'a mut CreateApplicationCommandOption {
+    option
+        .name(name)
+        .description(description)
+        .kind(CommandOptionType::Boolean)
+        .required(false)
+}
+
+fn position_option<'a>(
+    option: &'a mut CreateApplicationCommandOption,
+    name: &str,
+    description: &str,
+) -> &'a mut CreateApplicationCommandOption {
+    option
+        .name(name)
+        .description(description)
+        .kind(CommandOptionType::Integer)
+        .min_int_value(1)
+        .required(false)
+}
+
+/// Subcommand of the playlist command acting on the playlist with the given name.
+fn playlist_subcommand<'a>(
+    option: &'a mut CreateApplicationCommandOption,
+    name: &str,
+    description: &str,
+) -> &'a mut CreateApplicationCommandOption {
+    option
+        .name(name)
+        .description(description)
+        .kind(CommandOptionType::SubCommand)
+        .create_sub_option(|s| {
+            s.name("name")
+                .description("Name of the playlist")
+                .kind(CommandOptionType::String)
+                .required(true)
+        })
+        .create_sub_option(|s| flag_option(s, "mine", "Use your own playlists instead of the ones of the server"))
+}
+
+/// Option holding a 1-based index of the queue, suggesting the songs of the queue while typing.
+fn index_option<'a>(
+    option: &'a mut CreateApplicationCommandOption,
+    name: &str,
+    description: &str,
+    required: bool,
+) -> &'a mut CreateApplicationCommandOption {
+    option
+        .name(name)
+        .description(description)
+        .kind(CommandOptionType::Integer)
+        .min_int_value(1)
+        .set_autocomplete(true)
+        .required(required)
+}
+
+/// Runs a slash command with the same handler as its prefixed version.
+pub async fn run_slash_command(ctx: &Context, command: &ApplicationCommandInteraction) -> SerenityResult<()> {
+    // Searching and joining channels can take longer than the 3 seconds Discord waits for an answer
+    command.defer(&ctx.http).await?;
+
+    let invocation = Invocation::from_slash(command);
+    let options = &command.data.options;
+
+    info!("Slash command received {}", command.data.name);
+
+    let name = command.data.name.as_str();
+
+    if let Some(guild_id) = command.guild_id {
+        if DJ_COMMANDS.contains(&name) && !is_dj(ctx, &guild_id, invocation.author_id()).await {
+            invocation.say(ctx, NOT_A_DJ_MESSAGE).await?;
+
+            return Ok(());
+        }
+    }
+
+    let result = match name {
+        "play" => match play_request(options) {
+            Ok(request) => play_song_with_reaction(ctx, &invocation, &request, QueuePosition::Back).await,
+            Err(why) => Err(why.into()),
+        },
+        "pn" => match play_request(options) {
+            Ok(request) => play_song_with_reaction(ctx, &invocation, &request, QueuePosition::Front).await,
+            Err(why) => Err(why.into()),
+        },
+        "search" => search_command(ctx, &invocation, string_option(options, "query")).await,
+        "insert" => {
+            let index = index_value(options, "position");
+
+            match play_request(options) {
+                Ok(request) => insert_command(ctx, &invocation, index, &request).await,
+                Err(why) => Err(why.into()),
+            }
+        }
+        "pause" => pause_command(ctx, &invocation).await,
+        "unpause" => unpause_command(ctx, &invocation).await,
+        "next" => next_command(ctx, &invocation).await,
+        "stop" => stop_command(ctx, &invocation).await,
+        "queue" => queue_command(ctx, &invocation).await,
+        "np" => np_command(ctx, &invocation).await,
+        "shuffle" => shuffle_command(ctx, &invocation).await,
+        "loop" => loop_command(ctx, &invocation).await,
+        "resume" => resume_command(ctx, &invocation).await,
+        "previous" => previous_command(ctx, &invocation).await,
+        "history" => history_command(ctx, &invocation).await,
+        "replay" => replay_command(ctx, &invocation).await,
+        "help" => help_command(ctx, &invocation).await,
+        "seek" => seek_command(ctx, &invocation, string_option(options, "position")).await,
+        "volume" => {
+            let volume = int_option(options, "volume").map(|volume| volume.to_string()).unwrap_or_default();
+            volume_command(ctx, &invocation, &volume).await
+        }
+        "voteskip" => {
+            let percentage = int_option(options, "percentage").map(|percentage| percentage.to_string()).unwrap_or_default();
+            vote_skip_command(ctx, &invocation, &percentage).await
+        }
+        "playlist" => match options.first() {
+            Some(subcommand) => {
+                let request = PlaylistRequest::new(
+                    &subcommand.name,
+                    string_option(&subcommand.options, "name"),
+                    string_option(&subcommand.options, "query"),
+                    bool_option(&subcommand.options, "mine"),
+                );
+
+                match request {
+                    Ok(request) => playlist_command(ctx, &invocation, &request).await,
+                    Err(why) => Err(why.into()),
+                }
+            }
+            None => Err(CommandError::from("Missing subcommand of the playlist command")),
+        },
+        "maxsongs" => {
+            let songs = int_option(options, "songs").map(|songs| songs.to_string()).unwrap_or_default();
+            max_songs_command(ctx, &invocation, &songs).await
+        }
+        "djrole" => {
+            // Without a role the current one is shown, clearing it has to be asked for explicitly
+            let role = if bool_option(options, "off") { "off" } else { string_option(options, "role") };
+            dj_role_command(ctx, &invocation, role).await
+        }
+        "remove" => {
+            let from = index_value(options, "from");
+            let to = index_value(options, "to").or(from);
+            remove_command(ctx, &invocation, from.zip(to)).await
+        }
+        "move" => move_command(ctx, &invocation, index_value(options, "from"), index_value(options, "to")).await,
+        "swap" => swap_command(ctx, &invocation, index_value(options, "first"), index_value(options, "second")).await,
+        "goto" => goto_command(ctx, &invocation, index_value(options, "index")).await,
+        name => Err(CommandError::from(format!("Unknown slash command {name}"))),
+    };
+
+    invocation.finish(ctx, &result).await;
+
+    Ok(())
+}
+
+/// Suggests the songs of the queue for the index options, filtered by what has been typed so far, either the
+/// beginning of the index or part of the title.
+pub async fn autocomplete_queue_index(ctx: &Context, autocomplete: &AutocompleteInteraction) -> SerenityResult<()> {
+    let typed = autocomplete.data.options
+        .iter()
+        .find(|option| option.focused)
+        .and_then(|option| option.value.as_ref())
+        .map(|value| value.as_str().map(str::to_string).unwrap_or(value.to_string()))
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let choices: Vec<(String, i64)> = {
+        let data = ctx.data.read().await;
+
+        match autocomplete.guild_id.and_then(|guild_id| get_server(&data, &guild_id)) {
+            Some(server) => server.queue
+                .iter()
+                .enumerate()
+                .map(|(index, song)| (index + 1, song))
+                .filter(|(index, song)| {
+                    index.to_string().starts_with(&typed) || song.title.to_lowercase().contains(&typed)
+                })
+                .take(MAX_AUTOCOMPLETE_CHOICES)
+                .map(|(index, song)| {
+                    (truncate(&format!("{index}. {}", song.title), MAX_LABEL_LENGTH), index as i64)
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    };
+
+    autocomplete.create_autocomplete_response(&ctx.http, |r| {
+        for (name, index) in choices {
+            r.add_int_choice(name, index);
+        }
+
+        r
+    }).await
+}
+
+fn play_request(options: &[CommandDataOption]) -> Result<PlayRequest, DubaError> {
+    let playlist = PlaylistOptions {
+        from: index_value(options, "from"),
+        to: index_value(options, "to"),
+        limit: index_value(options, "limit"),
+        shuffle: bool_option(options, "shuffle"),
+        reverse: bool_option(options, "reverse"),
+    };
+
+    playlist.check_range()?;
+
+    Ok(PlayRequest {
+        whole_playlist: bool_option(options, "playlist"),
+        playlist,
+        ..PlayRequest::new(string_option(options, "query"))
+    })
+}
+
+fn find_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a CommandDataOption> {
+    options.iter().find(|option| option.name == name)
+}
+
+fn string_option<'a>(options: &'a [CommandDataOption], name: &str) -> &'a str {
+    find_option(options, name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+        .unwrap_or_default()
+}
+
+fn bool_option(options: &[CommandDataOption], name: &str) -> bool {
+    find_option(options, name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false)
+}
+
+fn int_option(options: &[CommandDataOption], name: &str) -> Option<i64> {
+    find_option(options, name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_i64())
+}
+
+fn index_value(options: &[CommandDataOption], name: &str) -> Option<usize> {
+    int_option(options, name).and_then(|index| usize::try_from(index).ok())
+}
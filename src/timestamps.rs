@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+/// Position requested by the seek command.
+pub enum SeekTarget {
+    Absolute(Duration),
+    Forward(Duration),
+    Backward(Duration),
+}
+
+impl SeekTarget {
+    /// Parses an absolute position (`1:30`) or an offset from the current one (`+30`, `-1:00`).
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+
+        if let Some(offset) = input.strip_prefix('+') {
+            parse_timestamp(offset).map(SeekTarget::Forward)
+        } else if let Some(offset) = input.strip_prefix('-') {
+            parse_timestamp(offset).map(SeekTarget::Backward)
+        } else {
+            parse_timestamp(input).map(SeekTarget::Absolute)
+        }
+    }
+
+    pub fn position_from(&self, current_position: Duration) -> Duration {
+        match self {
+            SeekTarget::Absolute(position) => *position,
+            SeekTarget::Forward(offset) => current_position.saturating_add(*offset),
+            SeekTarget::Backward(offset) => current_position.saturating_sub(*offset),
+        }
+    }
+}
+
+/// Parses a position given in seconds (`90`), as a clock (`1:30`, `1:02:03`) or in YouTube's format (`1m30s`).
+pub fn parse_timestamp(input: &str) -> Option<Duration> {
+    let input = input.trim();
+
+    if input.is_empty() {
+        return None;
+    }
+
+    if input.contains(':') {
+        let parts: Vec<&str> = input.split(':').collect();
+
+        if parts.len() > 3 {
+            return None;
+        }
+
+        let mut seconds: u64 = 0;
+
+        for part in parts {
+            seconds = seconds.checked_mul(60)?.checked_add(part.parse::<u64>().ok()?)?;
+        }
+
+        return Some(Duration::from_secs(seconds));
+    }
+
+    if let Ok(seconds) = input.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let value: u64 = number.parse().ok()?;
+        number.clear();
+
+        let unit_seconds = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+
+        seconds = seconds.checked_add(value.checked_mul(unit_seconds)?)?;
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(Duration::from_secs(seconds))
+}
+
+/// Formats a duration as `m:ss`, or `h:mm:ss` for durations of an hour or more.
+pub fn format_duration(duration: Duration) -> String {
+    let total_seconds = duration.as_secs();
+    let hours = total_seconds / 3600;
+    let minutes = (total_seconds % 3600) / 60;
+    let seconds = total_seconds % 60;
+
+    if hours > 0 {
+        format!("{hours}:{minutes:0>2}:{seconds:0>2}")
+    } else {
+        format!("{minutes}:{seconds:0>2}")
+    }
+}
+
+/// Formats the length of a song, which is unknown for live streams and some sites.
+pub fn format_length(duration: Option<Duration>) -> String {
+    duration.map(format_duration).unwrap_or("?".to_string())
+}
+
+/// Converts a length in seconds as reported by yt-dlp, which can be fractional. Missing, zero or invalid lengths
+/// (e.g. live streams) are unknown.
+pub fn duration_from_seconds(seconds: Option<f64>) -> Option<Duration> {
+    Duration::try_from_secs_f64(seconds?)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_durations_under_an_hour() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0:00");
+        assert_eq!(format_duration(Duration::from_secs(212)), "3:32");
+        assert_eq!(format_duration(Duration::from_secs_f64(59.9)), "0:59");
+    }
+
+    #[test]
+    fn formats_durations_of_hours() {
+        assert_eq!(format_duration(Duration::from_secs(3600)), "1:00:00");
+        assert_eq!(format_duration(Duration::from_secs(10_000)), "2:46:40");
+    }
+
+    #[test]
+    fn formats_unknown_lengths() {
+        assert_eq!(format_length(None), "?");
+        assert_eq!(format_length(Some(Duration::from_secs(75))), "1:15");
+    }
+
+    #[test]
+    fn converts_fractional_seconds() {
+        assert_eq!(duration_from_seconds(Some(212.5)), Some(Duration::from_millis(212_500)));
+        assert_eq!(duration_from_seconds(Some(90.0)), Some(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn invalid_seconds_are_unknown() {
+        assert_eq!(duration_from_seconds(None), None);
+        assert_eq!(duration_from_seconds(Some(0.0)), None);
+        assert_eq!(duration_from_seconds(Some(-1.0)), None);
+        assert_eq!(duration_from_seconds(Some(f64::NAN)), None);
+    }
+
+    #[test]
+    fn rejects_overflowing_timestamps() {
+        assert_eq!(parse_timestamp("1:30"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_timestamp("1m30s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_timestamp("18446744073709551615:59"), None);
+        assert_eq!(parse_timestamp("18446744073709551615h"), None);
+        assert_eq!(parse_timestamp("18446744073709551615s1s"), None);
+    }
+
+    #[test]
+    fn seeks_forward_without_overflowing() {
+        let target = SeekTarget::Forward(Duration::MAX);
+
+        assert_eq!(target.position_from(Duration::from_secs(30)), Duration::MAX);
+    }
+}
@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use crate::timestamps::parse_timestamp;
+
+/// What the input of the play commands refers to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UrlKind {
+    /// Search terms rather than a URL.
+    Search,
+    /// A single video or track, including YouTube shorts and youtu.be links.
+    Video,
+    /// A whole playlist, album or set.
+    Playlist,
+    /// A video opened from a playlist, which can be queued alone or along with its whole playlist.
+    VideoInPlaylist { video_url: String, playlist_url: String },
+    /// A video opened from a YouTube Mix, which are endless lists generated from the video.
+    Mix { video_url: String },
+}
+
+const YOUTUBE_HOSTS: [&str; 4] = ["youtube.com", "www.youtube.com", "m.youtube.com", "music.youtube.com"];
+const YOUTU_BE_HOST: &str = "youtu.be";
+
+/// Path segments used by other sites for their playlists, e.g. SoundCloud sets or Bandcamp albums.
+const PLAYLIST_PATH_SEGMENTS: [&str; 4] = ["playlist", "sets", "album", "albums"];
+
+/// Classifies the input of the play commands.
+pub fn classify(input: &str) -> UrlKind {
+    let url = match ParsedUrl::parse(input.trim()) {
+        Some(url) => url,
+        None => return UrlKind::Search,
+    };
+
+    if YOUTUBE_HOSTS.contains(&url.host.as_str()) {
+        classify_youtube(&url)
+    } else if url.host == YOUTU_BE_HOST {
+        let video_id = url.path_segments().next().unwrap_or_default().to_string();
+        classify_youtube_video(&url, &video_id)
+    } else if url.path_segments().any(|segment| PLAYLIST_PATH_SEGMENTS.contains(&segment)) {
+        UrlKind::Playlist
+    } else {
+        UrlKind::Video
+    }
+}
+
+/// Reads the start time of links like `https://youtu.be/<id>?t=90` or `https://www.youtube.com/watch?v=<id>&t=1m30s`.
+pub fn start_time_from_url(input: &str) -> Option<Duration> {
+    let url = ParsedUrl::parse(input.trim())?;
+
+    url.param("t")
+        .or(url.param("start"))
+        .and_then(parse_timestamp)
+}
+
+fn classify_youtube(url: &ParsedUrl) -> UrlKind {
+    let mut segments = url.path_segments();
+
+    match segments.next() {
+        // YouTube Music lists albums under browse
+        Some("playlist" | "browse") => UrlKind::Playlist,
+        Some("watch") => match url.param("v") {
+            Some(video_id) => classify_youtube_video(url, video_id),
+            None if url.param("list").is_some() => UrlKind::Playlist,
+            None => UrlKind::Video,
+        },
+        // Shorts and lives can't be opened from a playlist, the video ID is part of the path
+        _ => UrlKind::Video,
+    }
+}
+
+fn classify_youtube_video(url: &ParsedUrl, video_id: &str) -> UrlKind {
+    let list_id = match url.param("list") {
+        Some(list_id) => list_id,
+        None => return UrlKind::Video,
+    };
+
+    // The start time is kept so the video still starts where the link says
+    let video_url = match url.param("t") {
+        Some(start) => format!("https://www.youtube.com/watch?v={video_id}&t={start}"),
+        None => format!("https://www.youtube.com/watch?v={video_id}"),
+    };
+
+    // Mixes and radios have list IDs starting with RD
+    if list_id.starts_with("RD") {
+        UrlKind::Mix { video_url }
+    } else {
+        UrlKind::VideoInPlaylist {
+            video_url,
+            playlist_url: format!("https://www.youtube.com/playlist?list={list_id}"),
+        }
+    }
+}
+
+struct ParsedUrl<'a> {
+    host: String,
+    path: &'a str,
+    params: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> ParsedUrl<'a> {
+    fn parse(input: &'a str) -> Option<Self> {
+        let (scheme, rest) = input.split_once("://")?;
+
+        if !scheme.eq_ignore_ascii_case("https") && !scheme.eq_ignore_ascii_case("http") {
+            return None;
+        }
+
+        if rest.contains(char::is_whitespace) {
+            return None;
+        }
+
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (host, path) = address.split_once('/').unwrap_or((address, ""));
+
+        if host.is_empty() {
+            return None;
+        }
+
+        let params = query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .collect();
+
+        Some(Self {
+            host: host.to_lowercase(),
+            path,
+            params,
+        })
+    }
+
+    fn path_segments(&self) -> impl Iterator<Item = &'a str> {
+        self.path.split('/').filter(|segment| !segment.is_empty())
+    }
+
+    fn param(&self, key: &str) -> Option<&'a str> {
+        self.params
+            .iter()
+            .find(|(param_key, _)| *param_key == key)
+            .map(|(_, value)| *value)
+            .filter(|value| !value.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_terms_are_not_urls() {
+        assert_eq!(classify("never gonna give you up"), UrlKind::Search);
+        assert_eq!(classify("https:// spaced out"), UrlKind::Search);
+    }
+
+    #[test]
+    fn classifies_single_videos() {
+        assert_eq!(classify("https://www.youtube.com/watch?v=dQw4w9WgXcQ"), UrlKind::Video);
+        assert_eq!(classify("https://youtu.be/dQw4w9WgXcQ?t=42"), UrlKind::Video);
+        assert_eq!(classify("https://youtube.com/shorts/abcdefghijk"), UrlKind::Video);
+        assert_eq!(classify("https://soundcloud.com/artist/track-name"), UrlKind::Video);
+    }
+
+    #[test]
+    fn classifies_playlists() {
+        assert_eq!(classify("https://www.youtube.com/playlist?list=PLduba"), UrlKind::Playlist);
+        assert_eq!(classify("https://soundcloud.com/artist/sets/set-name"), UrlKind::Playlist);
+        assert_eq!(classify("https://artist.bandcamp.com/album/album-name"), UrlKind::Playlist);
+        assert_eq!(classify("https://music.youtube.com/browse/MPREb_duba"), UrlKind::Playlist);
+        assert_eq!(classify("HTTPS://WWW.YOUTUBE.COM/playlist?list=PLduba"), UrlKind::Playlist);
+    }
+
+    #[test]
+    fn classifies_videos_in_playlists() {
+        let expected = UrlKind::VideoInPlaylist {
+            video_url: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+            playlist_url: "https://www.youtube.com/playlist?list=PLduba".to_string(),
+        };
+
+        assert_eq!(classify("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLduba&index=3"), expected);
+        assert_eq!(classify("https://www.youtube.com/watch?list=PLduba&v=dQw4w9WgXcQ"), expected);
+        assert_eq!(classify("https://youtu.be/dQw4w9WgXcQ?list=PLduba"), expected);
+    }
+
+    #[test]
+    fn classifies_mixes() {
+        let expected = UrlKind::Mix {
+            video_url: "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=10".to_string(),
+        };
+
+        assert_eq!(classify("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=RDdQw4w9WgXcQ&t=10"), expected);
+    }
+
+    #[test]
+    fn reads_start_times() {
+        let cases = [
+            ("https://youtu.be/dQw4w9WgXcQ?t=90", Some(90)),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=1m30s", Some(90)),
+            ("https://www.youtube.com/embed/dQw4w9WgXcQ?start=42", Some(42)),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ", None),
+            ("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=soon", None),
+            ("never gonna give you up t=10", None),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(start_time_from_url(input), expected.map(Duration::from_secs), "{input}");
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashSet;
+
+use serenity::model::id::UserId;
+
+use crate::models::DEFAULT_SKIP_THRESHOLD;
+
+/// Votes to skip the current track of a guild. They are cleared whenever the track changes.
+#[derive(Default)]
+pub struct SkipVotes {
+    voters: HashSet<UserId>,
+}
+
+impl SkipVotes {
+    /// Registers the vote of a user, returning false if they had already voted.
+    pub fn add(&mut self, user_id: UserId) -> bool {
+        self.voters.insert(user_id)
+    }
+
+    /// Counts the votes of the users that are still listening, as the ones who left shouldn't keep counting.
+    pub fn count_among(&self, listeners: &[UserId]) -> usize {
+        listeners.iter().filter(|listener| self.voters.contains(listener)).count()
+    }
+
+    pub fn clear(&mut self) {
+        self.voters.clear();
+    }
+}
+
+/// Votes needed to skip with the given number of listeners and threshold percentage. At least one vote is always
+/// needed.
+pub fn required_votes(listeners_count: usize, threshold: u32) -> usize {
+    (listeners_count * threshold as usize).div_ceil(100).max(1)
+}
+
+/// Reads the vote skip setting given to the voteskip command: `on`, `off` or a percentage, with or without `%`.
+/// A threshold of 0 turns vote skipping off, the same as `off`. Returns None if the input is not valid.
+pub fn parse_skip_threshold(input: &str) -> Option<Option<u32>> {
+    match input {
+        "on" => Some(Some(DEFAULT_SKIP_THRESHOLD)),
+        "off" => Some(None),
+        _ => match input.trim_end_matches('%').parse::<u32>() {
+            Ok(0) => Some(None),
+            Ok(threshold) if threshold <= 100 => Some(Some(threshold)),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requires_votes_of_a_percentage_of_the_listeners() {
+        let cases = [
+            (4, 50, 2),
+            (5, 50, 3),
+            (3, 100, 3),
+            (10, 1, 1),
+            (1, 50, 1),
+            (0, 50, 1),
+        ];
+
+        for (listeners_count, threshold, expected) in cases {
+            assert_eq!(required_votes(listeners_count, threshold), expected);
+        }
+    }
+
+    #[test]
+    fn counts_the_votes_of_the_listeners() {
+        let mut votes = SkipVotes::default();
+
+        assert!(votes.add(UserId(1)));
+        assert!(!votes.add(UserId(1)));
+        assert!(votes.add(UserId(2)));
+
+        assert_eq!(votes.count_among(&[UserId(1), UserId(3)]), 1);
+
+        votes.clear();
+
+        assert_eq!(votes.count_among(&[UserId(1), UserId(2)]), 0);
+    }
+
+    #[test]
+    fn parses_skip_thresholds() {
+        let cases = [
+            ("on", Some(Some(DEFAULT_SKIP_THRESHOLD))),
+            ("off", Some(None)),
+            ("0", Some(None)),
+            ("0%", Some(None)),
+            ("75", Some(Some(75))),
+            ("75%", Some(Some(75))),
+            ("100%", Some(Some(100))),
+            ("101", None),
+            ("-5", None),
+            ("half", None),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_skip_threshold(input), expected, "{input}");
+        }
+    }
+}